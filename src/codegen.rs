@@ -0,0 +1,1255 @@
+//! Generates idiomatic Rust types and (de)serialization methods from a parsed
+//! `FileDescriptor`.
+//!
+//! The mapping mirrors what quick-protobuf's own generator does: `optional`
+//! fields become `Option<T>`, `repeated` fields become `Vec<T>`, `map<K, V>`
+//! fields become a `HashMap<K, V>`, and `oneof` groups become a Rust `enum`.
+//! A file's `package` becomes a chain of nested `mod`s wrapping the whole
+//! output, and each message that declares nested messages or enums gets its
+//! own `mod` (named after the message, converted to snake_case) so that
+//! `outer::Inner` paths produced for cross-references actually correspond to
+//! real generated modules, and two different parents' same-named nested
+//! types don't collide.
+//!
+//! Every generated message also gets `write_to`/`merge_from` methods that
+//! encode/decode the standard protobuf wire format, via a small `wire` helper
+//! module emitted once per file. `Field::packed` controls whether a repeated
+//! scalar field is written as one length-delimited run or as individual
+//! tag/value pairs; `merge_from` accepts either encoding regardless of how
+//! the field was declared, since readers must always tolerate both.
+
+use std::io::{self, Write};
+
+use super::resolve::{build_symbol_table, resolve_lenient, Definition, SymbolTable};
+use super::{Enumeration, Field, FieldType, FileDescriptor, Message, OneOf, Rule};
+
+/// Errors that can occur while generating Rust source from a `FileDescriptor`.
+#[derive(Debug)]
+pub enum CodegenError {
+    /// Writing to the output sink failed.
+    Io(io::Error),
+    /// A field's number or name collides with one of the message's
+    /// `reserved` entries.
+    ReservedCollision {
+        message: String,
+        field: String,
+        detail: String,
+    },
+}
+
+impl From<io::Error> for CodegenError {
+    fn from(e: io::Error) -> Self {
+        CodegenError::Io(e)
+    }
+}
+
+/// Threaded through every codegen function: the file's `package` (needed to
+/// turn a resolved `pkg.Outer.Inner` name into the right `pkg::outer::Inner`
+/// path) and the symbol table used to tell an enum field apart from a
+/// message field, since both are parsed as the same `FieldType::MessageOrEnum`.
+struct Ctx<'a> {
+    package: String,
+    symtab: &'a SymbolTable<'a>,
+}
+
+const WIRE_PRELUDE: &str = r#"pub mod wire {
+    pub fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+        loop {
+            let byte = (value & 0x7f) as u8;
+            value >>= 7;
+            if value == 0 {
+                out.push(byte);
+                break;
+            }
+            out.push(byte | 0x80);
+        }
+    }
+
+    pub fn write_tag(out: &mut Vec<u8>, field_number: u32, wire_type: u8) {
+        write_varint(out, ((field_number as u64) << 3) | wire_type as u64);
+    }
+
+    pub fn read_varint(bytes: &[u8], mut pos: usize) -> (u64, usize) {
+        let mut result = 0u64;
+        let mut shift = 0;
+        loop {
+            let byte = bytes[pos];
+            pos += 1;
+            result |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                break;
+            }
+            shift += 7;
+        }
+        (result, pos)
+    }
+
+    pub fn read_fixed32(bytes: &[u8], pos: usize) -> (u32, usize) {
+        let mut buf = [0u8; 4];
+        buf.copy_from_slice(&bytes[pos..pos + 4]);
+        (u32::from_le_bytes(buf), pos + 4)
+    }
+
+    pub fn read_fixed64(bytes: &[u8], pos: usize) -> (u64, usize) {
+        let mut buf = [0u8; 8];
+        buf.copy_from_slice(&bytes[pos..pos + 8]);
+        (u64::from_le_bytes(buf), pos + 8)
+    }
+
+    pub fn read_length_delimited(bytes: &[u8], pos: usize) -> (&[u8], usize) {
+        let (len, next) = read_varint(bytes, pos);
+        (&bytes[next..next + len as usize], next + len as usize)
+    }
+
+    /// `field_number` is only consulted for `wire_type == 3` (the start of a
+    /// `group`), to find the matching end-group tag - it must be the field
+    /// number the caller read off the *opening* tag, not anything derived
+    /// from the group's contents, or this searches for the wrong end-group
+    /// tag on any legitimately-encoded group this generated struct doesn't
+    /// know about.
+    pub fn skip_field(bytes: &[u8], pos: usize, wire_type: u8, field_number: u32) -> usize {
+        match wire_type {
+            0 => read_varint(bytes, pos).1,
+            1 => pos + 8,
+            2 => {
+                let (len, next) = read_varint(bytes, pos);
+                next + len as usize
+            }
+            3 => read_group(bytes, pos, field_number).1,
+            5 => pos + 4,
+            _ => bytes.len(),
+        }
+    }
+
+    /// Scans past a `group`'s contents to the end-group tag matching
+    /// `field_number`, returning the content slice and the position just
+    /// past the end-group tag.
+    pub fn read_group(bytes: &[u8], pos: usize, field_number: u32) -> (&[u8], usize) {
+        let mut cur = pos;
+        loop {
+            let content_end = cur;
+            let (tag, next) = read_varint(bytes, cur);
+            cur = next;
+            let fnum = (tag >> 3) as u32;
+            let wtype = (tag & 0x7) as u8;
+            if wtype == 4 && fnum == field_number {
+                return (&bytes[pos..content_end], cur);
+            }
+            if wtype == 3 {
+                let (_, after) = read_group(bytes, cur, fnum);
+                cur = after;
+            } else {
+                cur = skip_field(bytes, cur, wtype, fnum);
+            }
+        }
+    }
+
+    pub fn zigzag_encode32(value: i32) -> u64 {
+        (((value << 1) ^ (value >> 31)) as u32) as u64
+    }
+
+    pub fn zigzag_encode64(value: i64) -> u64 {
+        ((value << 1) ^ (value >> 63)) as u64
+    }
+
+    pub fn zigzag_decode32(value: u64) -> i32 {
+        ((value as u32 >> 1) as i32) ^ -((value & 1) as i32)
+    }
+
+    pub fn zigzag_decode64(value: u64) -> i64 {
+        ((value >> 1) as i64) ^ -((value & 1) as i64)
+    }
+}"#;
+
+impl FileDescriptor {
+    /// Walks this descriptor and emits Rust struct/enum definitions plus
+    /// `write_to`/`merge_from` (de)serialization methods for every message
+    /// and enum it declares.
+    ///
+    /// This only has `self`'s own declarations to resolve against, so a field
+    /// referencing a type declared in an imported file keeps its name as
+    /// written rather than its fully-qualified form, and - since that leaves
+    /// no way to tell an unresolved enum reference apart from a message one -
+    /// is generated as a message field. Use `generate_rust_with` (or
+    /// `ImportTree::generate_rust`) when other files need to be in scope.
+    ///
+    /// Emission fails as soon as a message is found whose fields collide with
+    /// its own `reserved_nums`/`reserved_names`, since such a descriptor could
+    /// never have been produced by a spec-compliant `.proto` file.
+    pub fn generate_rust(&self, out: &mut impl Write) -> Result<(), CodegenError> {
+        let symtab = build_symbol_table(self);
+        self.generate_rust_with(&symtab, out)
+    }
+
+    /// Like `generate_rust`, but resolves type references - and tells an enum
+    /// field apart from a message field - against a caller-supplied symbol
+    /// table instead of one built from `self` alone, e.g.
+    /// `ImportTree::full_symbol_table`, so a reference into another file in
+    /// the same set is classified correctly. Resolution is best-effort per
+    /// field (see `resolve_lenient`), so one dangling reference doesn't
+    /// prevent every other, independently-resolvable field from getting its
+    /// fully-qualified path too.
+    pub fn generate_rust_with(&self, symtab: &SymbolTable, out: &mut impl Write) -> Result<(), CodegenError> {
+        let resolved = resolve_lenient(self, symtab);
+        let ctx = Ctx {
+            package: resolved.package.clone(),
+            symtab,
+        };
+
+        writeln!(out, "{}", WIRE_PRELUDE)?;
+
+        let mod_path: Vec<&str> = if resolved.package.is_empty() {
+            Vec::new()
+        } else {
+            resolved.package.split('.').collect()
+        };
+
+        for m in &mod_path {
+            writeln!(out, "pub mod {} {{", m)?;
+            writeln!(out, "    use super::*;")?;
+        }
+
+        for enumeration in &resolved.enums {
+            write_enum(out, enumeration)?;
+        }
+        for message in &resolved.messages {
+            write_message(out, message, &ctx)?;
+        }
+
+        for _ in &mod_path {
+            writeln!(out, "}}")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// A resolved `MessageOrEnum` name always appears in `symtab` (that's what
+/// resolving it against this same table means) - mirrors
+/// `descriptor_proto::is_enum`.
+fn is_local_enum(name: &str, ctx: &Ctx) -> bool {
+    matches!(ctx.symtab.get(name), Some(Definition::Enumeration(_)))
+}
+
+fn check_reserved(message: &Message) -> Result<(), CodegenError> {
+    for field in message.fields.iter().chain(message.oneofs.iter().flat_map(|o| o.fields.iter())) {
+        if message.reserved_nums.iter().any(|r| r.contains(&field.number)) {
+            return Err(CodegenError::ReservedCollision {
+                message: message.name.clone(),
+                field: field.name.clone(),
+                detail: format!("field number {} is reserved", field.number),
+            });
+        }
+        if message.reserved_names.iter().any(|n| n == &field.name) {
+            return Err(CodegenError::ReservedCollision {
+                message: message.name.clone(),
+                field: field.name.clone(),
+                detail: format!("field name {:?} is reserved", field.name),
+            });
+        }
+    }
+    Ok(())
+}
+
+fn write_enum(out: &mut impl Write, e: &Enumeration) -> Result<(), CodegenError> {
+    if e.values.is_empty() {
+        writeln!(out, "#[derive(Debug, Clone, Copy, PartialEq, Eq)]")?;
+    } else {
+        writeln!(out, "#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]")?;
+    }
+    writeln!(out, "pub enum {} {{", e.name)?;
+    for (i, value) in e.values.iter().enumerate() {
+        if i == 0 {
+            writeln!(out, "    #[default]")?;
+        }
+        writeln!(out, "    {} = {},", value.name, value.number)?;
+    }
+    writeln!(out, "}}")?;
+
+    writeln!(out, "impl {} {{", e.name)?;
+    writeln!(out, "    pub fn from_i32(value: i32) -> Option<Self> {{")?;
+    writeln!(out, "        match value {{")?;
+    for value in &e.values {
+        writeln!(out, "            {} => Some({}::{}),", value.number, e.name, value.name)?;
+    }
+    writeln!(out, "            _ => None,")?;
+    writeln!(out, "        }}")?;
+    writeln!(out, "    }}")?;
+    writeln!(out, "}}")?;
+    Ok(())
+}
+
+fn write_message(out: &mut impl Write, message: &Message, ctx: &Ctx) -> Result<(), CodegenError> {
+    check_reserved(message)?;
+
+    writeln!(out, "#[derive(Debug, Clone, Default, PartialEq)]")?;
+    writeln!(out, "pub struct {} {{", message.name)?;
+    for field in &message.fields {
+        writeln!(out, "    pub {}: {},", field.name, field_rust_type(field, ctx))?;
+    }
+    for oneof in &message.oneofs {
+        writeln!(out, "    pub {}: Option<{}>,", oneof.name, oneof_enum_name(oneof))?;
+    }
+    writeln!(out, "}}")?;
+
+    writeln!(out, "impl {} {{", message.name)?;
+    emit_write_to(out, message, ctx)?;
+    emit_merge_from(out, message, ctx)?;
+    writeln!(out, "}}")?;
+
+    for oneof in &message.oneofs {
+        write_oneof_enum(out, oneof, ctx)?;
+    }
+    if !message.messages.is_empty() || !message.enums.is_empty() {
+        writeln!(out, "pub mod {} {{", mod_name(&message.name))?;
+        writeln!(out, "    use super::*;")?;
+        for enumeration in &message.enums {
+            write_enum(out, enumeration)?;
+        }
+        for nested in &message.messages {
+            write_message(out, nested, ctx)?;
+        }
+        writeln!(out, "}}")?;
+    }
+    for field in group_fields_of(message) {
+        let synthetic = Message {
+            name: group_struct_name(field),
+            fields: group_fields_clone(field),
+            ..Message::default()
+        };
+        write_message(out, &synthetic, ctx)?;
+    }
+
+    Ok(())
+}
+
+/// Every `group`-typed field reachable from `message`'s own field list and
+/// from each of its oneofs - a oneof member that's a `group` needs its
+/// synthetic struct emitted exactly like a top-level one, since
+/// `write_oneof_enum` references it by name either way.
+fn group_fields_of(message: &Message) -> Vec<&Field> {
+    let mut fields: Vec<&Field> = message
+        .fields
+        .iter()
+        .filter(|f| matches!(f.typ, FieldType::Group(_)))
+        .collect();
+    for oneof in &message.oneofs {
+        fields.extend(oneof.fields.iter().filter(|f| matches!(f.typ, FieldType::Group(_))));
+    }
+    fields
+}
+
+fn group_fields_clone(field: &Field) -> Vec<Field> {
+    match field.typ {
+        FieldType::Group(ref fields) => fields.clone(),
+        _ => unreachable!("group_fields_of only yields Group-typed fields"),
+    }
+}
+
+fn oneof_enum_name(oneof: &OneOf) -> String {
+    format!("{}OneOf", capitalize(&oneof.name))
+}
+
+fn group_struct_name(field: &Field) -> String {
+    capitalize(&field.name)
+}
+
+fn write_oneof_enum(out: &mut impl Write, oneof: &OneOf, ctx: &Ctx) -> Result<(), CodegenError> {
+    writeln!(out, "#[derive(Debug, Clone, PartialEq)]")?;
+    writeln!(out, "pub enum {} {{", oneof_enum_name(oneof))?;
+    for field in &oneof.fields {
+        // A oneof member holds the bare inner type, never `Option<T>`/`Vec<T>`:
+        // reuse `field_rust_type` (which already special-cases `Group`/`Map`)
+        // under `Rule::Required` rather than calling `field_type_rust_name`
+        // directly, so a `group` member doesn't hit its `unreachable!()` arm.
+        let bare = Field {
+            rule: Rule::Required,
+            ..field.clone()
+        };
+        writeln!(out, "    {}({}),", capitalize(&field.name), field_rust_type(&bare, ctx))?;
+    }
+    writeln!(out, "}}")?;
+    Ok(())
+}
+
+fn capitalize(name: &str) -> String {
+    name.split('_')
+        .filter(|s| !s.is_empty())
+        .map(|part| {
+            let mut chars = part.chars();
+            match chars.next() {
+                Some(first) => first.to_uppercase().collect::<String>() + chars.as_str(),
+                None => String::new(),
+            }
+        })
+        .collect()
+}
+
+/// Converts a PascalCase message name into the snake_case `mod` name that
+/// wraps its nested messages, e.g. `OuterMessage` -> `outer_message`.
+fn mod_name(name: &str) -> String {
+    let mut out = String::new();
+    for (i, c) in name.chars().enumerate() {
+        if c.is_uppercase() {
+            if i != 0 {
+                out.push('_');
+            }
+            out.extend(c.to_lowercase());
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+/// Turns a fully-qualified `MessageOrEnum` name into the Rust path that
+/// actually exists in the generated output. All code this function's result
+/// is spliced into is already nested inside the file's own `package` mods
+/// (see `generate_rust`), so a reference into this same package has its
+/// `package` prefix stripped rather than re-emitted - re-emitting it would
+/// mean writing e.g. `pkg::Other` from code that's already inside `mod pkg`,
+/// which Rust rejects (that's not how an absolute path from here would
+/// start). Every remaining segment but the last - each one a nested
+/// message's name - is converted to snake_case (see `mod_name`) to match the
+/// `mod` that wraps that message's nested types.
+fn qualified_path(name: &str, package: &str) -> String {
+    let trimmed = if !package.is_empty() && name.starts_with(package) && name[package.len()..].starts_with('.') {
+        &name[package.len() + 1..]
+    } else {
+        name
+    };
+
+    let mut parts: Vec<&str> = trimmed.split('.').filter(|s| !s.is_empty()).collect();
+    let last = parts.pop().unwrap_or(trimmed).to_string();
+
+    let mut segments: Vec<String> = parts.into_iter().map(mod_name).collect();
+    segments.push(last);
+    segments.join("::")
+}
+
+fn field_rust_type(field: &Field, ctx: &Ctx) -> String {
+    if let FieldType::Map(ref kv) = field.typ {
+        let (ref k, ref v) = **kv;
+        return format!(
+            "::std::collections::HashMap<{}, {}>",
+            field_type_rust_name(k, ctx),
+            field_type_rust_name(v, ctx)
+        );
+    }
+
+    let inner = if let FieldType::Group(_) = field.typ {
+        group_struct_name(field)
+    } else {
+        field_type_rust_name(&field.typ, ctx)
+    };
+
+    match field.rule {
+        Rule::Repeated => format!("Vec<{}>", inner),
+        Rule::Required => inner,
+        // Every optional field, whatever its type and whether or not it has
+        // an explicit default, is wrapped in `Option<T>`, even under proto3's
+        // implicit-presence rules.
+        Rule::Optional => format!("Option<{}>", inner),
+    }
+}
+
+fn field_type_rust_name(typ: &FieldType, ctx: &Ctx) -> String {
+    match *typ {
+        FieldType::Int32 | FieldType::Sint32 | FieldType::Sfixed32 => "i32".to_string(),
+        FieldType::Int64 | FieldType::Sint64 | FieldType::Sfixed64 => "i64".to_string(),
+        FieldType::Uint32 | FieldType::Fixed32 => "u32".to_string(),
+        FieldType::Uint64 | FieldType::Fixed64 => "u64".to_string(),
+        FieldType::Bool => "bool".to_string(),
+        FieldType::Float => "f32".to_string(),
+        FieldType::Double => "f64".to_string(),
+        FieldType::String | FieldType::RefCountedString => "String".to_string(),
+        FieldType::Bytes | FieldType::RefCountedBytes => "Vec<u8>".to_string(),
+        FieldType::Group(_) => unreachable!("group fields are rewritten by field_rust_type"),
+        FieldType::Map(..) => unreachable!("map fields are rewritten by field_rust_type"),
+        FieldType::MessageOrEnum(ref name) => qualified_path(name, &ctx.package),
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum WireCategory {
+    Varint,
+    Fixed32,
+    Fixed64,
+    LengthDelimited,
+}
+
+fn wire_category(typ: &FieldType, ctx: &Ctx) -> WireCategory {
+    match *typ {
+        FieldType::Int32
+        | FieldType::Int64
+        | FieldType::Uint32
+        | FieldType::Uint64
+        | FieldType::Sint32
+        | FieldType::Sint64
+        | FieldType::Bool => WireCategory::Varint,
+        FieldType::Fixed32 | FieldType::Sfixed32 | FieldType::Float => WireCategory::Fixed32,
+        FieldType::Fixed64 | FieldType::Sfixed64 | FieldType::Double => WireCategory::Fixed64,
+        FieldType::String | FieldType::RefCountedString | FieldType::Bytes | FieldType::RefCountedBytes => {
+            WireCategory::LengthDelimited
+        }
+        FieldType::MessageOrEnum(ref name) => {
+            if is_local_enum(name, ctx) {
+                WireCategory::Varint
+            } else {
+                WireCategory::LengthDelimited
+            }
+        }
+        FieldType::Group(_) | FieldType::Map(..) => unreachable!("group/map fields are encoded separately"),
+    }
+}
+
+fn wire_type_number(category: WireCategory) -> u8 {
+    match category {
+        WireCategory::Varint => 0,
+        WireCategory::Fixed64 => 1,
+        WireCategory::LengthDelimited => 2,
+        WireCategory::Fixed32 => 5,
+    }
+}
+
+/// Returns the statement(s) that append `value_var`'s wire-format bytes to
+/// `buf` - no tag, just the payload, so this can be reused both for a single
+/// tagged field and for one element inside a packed run.
+fn emit_raw_write(buf: &str, value_var: &str, typ: &FieldType, category: WireCategory) -> String {
+    let v = value_var;
+    match category {
+        WireCategory::Varint => {
+            let expr = match *typ {
+                FieldType::Int32 => format!("*{} as i64 as u64", v),
+                FieldType::Int64 => format!("*{} as u64", v),
+                FieldType::Uint32 | FieldType::Uint64 => format!("*{} as u64", v),
+                FieldType::Sint32 => format!("wire::zigzag_encode32(*{})", v),
+                FieldType::Sint64 => format!("wire::zigzag_encode64(*{})", v),
+                FieldType::Bool => format!("if *{} {{ 1 }} else {{ 0 }}", v),
+                FieldType::MessageOrEnum(_) => format!("*{} as i32 as u64", v),
+                _ => unreachable!("non-varint type classified as Varint"),
+            };
+            format!("wire::write_varint({}, {});", buf, expr)
+        }
+        WireCategory::Fixed32 => {
+            let expr = match *typ {
+                FieldType::Float => format!("{}.to_le_bytes()", v),
+                _ => format!("(*{} as u32).to_le_bytes()", v),
+            };
+            format!("{}.extend_from_slice(&{});", buf, expr)
+        }
+        WireCategory::Fixed64 => {
+            let expr = match *typ {
+                FieldType::Double => format!("{}.to_le_bytes()", v),
+                _ => format!("(*{} as u64).to_le_bytes()", v),
+            };
+            format!("{}.extend_from_slice(&{});", buf, expr)
+        }
+        WireCategory::LengthDelimited => match *typ {
+            FieldType::String | FieldType::RefCountedString => format!(
+                "wire::write_varint({buf}, {v}.len() as u64); {buf}.extend_from_slice({v}.as_bytes());",
+                buf = buf,
+                v = v
+            ),
+            FieldType::Bytes | FieldType::RefCountedBytes => format!(
+                "wire::write_varint({buf}, {v}.len() as u64); {buf}.extend_from_slice({v});",
+                buf = buf,
+                v = v
+            ),
+            FieldType::MessageOrEnum(_) => format!(
+                "{{ let mut nested = Vec::new(); {v}.write_to(&mut nested); wire::write_varint({buf}, nested.len() as u64); {buf}.extend_from_slice(&nested); }}",
+                buf = buf,
+                v = v
+            ),
+            _ => unreachable!("non-length-delimited type classified as LengthDelimited"),
+        },
+    }
+}
+
+fn emit_tagged_write(buf: &str, number: i32, value_var: &str, typ: &FieldType, category: WireCategory) -> String {
+    format!(
+        "wire::write_tag({buf}, {num}, {wt}); {raw}",
+        buf = buf,
+        num = number,
+        wt = wire_type_number(category),
+        raw = emit_raw_write(buf, value_var, typ, category)
+    )
+}
+
+fn write_encode_field(out: &mut impl Write, field: &Field, ctx: &Ctx) -> Result<(), CodegenError> {
+    match field.typ {
+        FieldType::Map(ref kv) => emit_map_encode(out, field, kv, ctx),
+        FieldType::Group(_) => emit_group_encode(out, field),
+        ref other => emit_scalar_encode(out, field, other, ctx),
+    }
+}
+
+fn emit_scalar_encode(out: &mut impl Write, field: &Field, typ: &FieldType, ctx: &Ctx) -> Result<(), CodegenError> {
+    let category = wire_category(typ, ctx);
+    match field.rule {
+        Rule::Required => {
+            writeln!(
+                out,
+                "    {{ let v = &self.{}; {} }}",
+                field.name,
+                emit_tagged_write("out", field.number, "v", typ, category)
+            )?;
+        }
+        Rule::Optional => {
+            writeln!(
+                out,
+                "    if let Some(ref v) = self.{} {{ {} }}",
+                field.name,
+                emit_tagged_write("out", field.number, "v", typ, category)
+            )?;
+        }
+        Rule::Repeated => {
+            let packed_ok = matches!(category, WireCategory::Varint | WireCategory::Fixed32 | WireCategory::Fixed64);
+            // proto3 repeated scalar fields default to packed; an explicit
+            // `[packed = false]` is the only thing that turns it off.
+            if packed_ok && field.packed != Some(false) {
+                writeln!(out, "    if !self.{}.is_empty() {{", field.name)?;
+                writeln!(out, "        let mut packed = Vec::new();")?;
+                writeln!(
+                    out,
+                    "        for v in &self.{} {{ {} }}",
+                    field.name,
+                    emit_raw_write("packed", "v", typ, category)
+                )?;
+                writeln!(out, "        wire::write_tag(out, {}, 2);", field.number)?;
+                writeln!(out, "        wire::write_varint(out, packed.len() as u64);")?;
+                writeln!(out, "        out.extend_from_slice(&packed);")?;
+                writeln!(out, "    }}")?;
+            } else {
+                writeln!(
+                    out,
+                    "    for v in &self.{} {{ {} }}",
+                    field.name,
+                    emit_tagged_write("out", field.number, "v", typ, category)
+                )?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn emit_group_encode(out: &mut impl Write, field: &Field) -> Result<(), CodegenError> {
+    let num = field.number;
+    match field.rule {
+        Rule::Required => writeln!(
+            out,
+            "    wire::write_tag(out, {num}, 3); self.{name}.write_to(out); wire::write_tag(out, {num}, 4);",
+            num = num,
+            name = field.name
+        )?,
+        Rule::Optional => writeln!(
+            out,
+            "    if let Some(ref v) = self.{name} {{ wire::write_tag(out, {num}, 3); v.write_to(out); wire::write_tag(out, {num}, 4); }}",
+            num = num,
+            name = field.name
+        )?,
+        Rule::Repeated => writeln!(
+            out,
+            "    for v in &self.{name} {{ wire::write_tag(out, {num}, 3); v.write_to(out); wire::write_tag(out, {num}, 4); }}",
+            num = num,
+            name = field.name
+        )?,
+    }
+    Ok(())
+}
+
+fn emit_map_encode(out: &mut impl Write, field: &Field, kv: &(FieldType, FieldType), ctx: &Ctx) -> Result<(), CodegenError> {
+    let key_cat = wire_category(&kv.0, ctx);
+    let val_cat = wire_category(&kv.1, ctx);
+    writeln!(out, "    for (k, v) in &self.{} {{", field.name)?;
+    writeln!(out, "        let mut entry = Vec::new();")?;
+    writeln!(
+        out,
+        "        wire::write_tag(&mut entry, 1, {}); {}",
+        wire_type_number(key_cat),
+        emit_raw_write("&mut entry", "k", &kv.0, key_cat)
+    )?;
+    writeln!(
+        out,
+        "        wire::write_tag(&mut entry, 2, {}); {}",
+        wire_type_number(val_cat),
+        emit_raw_write("&mut entry", "v", &kv.1, val_cat)
+    )?;
+    writeln!(out, "        wire::write_tag(out, {}, 2);", field.number)?;
+    writeln!(out, "        wire::write_varint(out, entry.len() as u64);")?;
+    writeln!(out, "        out.extend_from_slice(&entry);")?;
+    writeln!(out, "    }}")?;
+    Ok(())
+}
+
+fn emit_oneof_encode(out: &mut impl Write, oneof: &OneOf, ctx: &Ctx) -> Result<(), CodegenError> {
+    writeln!(out, "    if let Some(ref oneof_value) = self.{} {{", oneof.name)?;
+    writeln!(out, "        match oneof_value {{")?;
+    for field in &oneof.fields {
+        let body = match field.typ {
+            FieldType::Group(_) => format!(
+                "wire::write_tag(out, {num}, 3); inner.write_to(out); wire::write_tag(out, {num}, 4);",
+                num = field.number
+            ),
+            FieldType::Map(..) => String::new(),
+            ref other => {
+                let category = wire_category(other, ctx);
+                emit_tagged_write("out", field.number, "inner", other, category)
+            }
+        };
+        writeln!(
+            out,
+            "            {}::{}(ref inner) => {{ {} }}",
+            oneof_enum_name(oneof),
+            capitalize(&field.name),
+            body
+        )?;
+    }
+    writeln!(out, "        }}")?;
+    writeln!(out, "    }}")?;
+    Ok(())
+}
+
+fn emit_write_to(out: &mut impl Write, message: &Message, ctx: &Ctx) -> Result<(), CodegenError> {
+    writeln!(out, "    pub fn write_to(&self, out: &mut Vec<u8>) {{")?;
+    for field in &message.fields {
+        write_encode_field(out, field, ctx)?;
+    }
+    for oneof in &message.oneofs {
+        emit_oneof_encode(out, oneof, ctx)?;
+    }
+    writeln!(out, "    }}")?;
+    Ok(())
+}
+
+fn decode_value_expr(typ: &FieldType, ctx: &Ctx, bytes_var: &str, pos_var: &str) -> String {
+    let b = bytes_var;
+    let p = pos_var;
+    match wire_category(typ, ctx) {
+        WireCategory::Varint => match *typ {
+            FieldType::Int32 => format!("{{ let (raw, next) = wire::read_varint({b}, {p}); {p} = next; raw as i64 as i32 }}", b = b, p = p),
+            FieldType::Int64 => format!("{{ let (raw, next) = wire::read_varint({b}, {p}); {p} = next; raw as i64 }}", b = b, p = p),
+            FieldType::Uint32 => format!("{{ let (raw, next) = wire::read_varint({b}, {p}); {p} = next; raw as u32 }}", b = b, p = p),
+            FieldType::Uint64 => format!("{{ let (raw, next) = wire::read_varint({b}, {p}); {p} = next; raw }}", b = b, p = p),
+            FieldType::Sint32 => format!("{{ let (raw, next) = wire::read_varint({b}, {p}); {p} = next; wire::zigzag_decode32(raw) }}", b = b, p = p),
+            FieldType::Sint64 => format!("{{ let (raw, next) = wire::read_varint({b}, {p}); {p} = next; wire::zigzag_decode64(raw) }}", b = b, p = p),
+            FieldType::Bool => format!("{{ let (raw, next) = wire::read_varint({b}, {p}); {p} = next; raw != 0 }}", b = b, p = p),
+            FieldType::MessageOrEnum(ref name) => format!(
+                "{{ let (raw, next) = wire::read_varint({b}, {p}); {p} = next; {ty}::from_i32(raw as i32).unwrap_or_default() }}",
+                b = b,
+                p = p,
+                ty = qualified_path(name, &ctx.package)
+            ),
+            _ => unreachable!(),
+        },
+        WireCategory::Fixed32 => match *typ {
+            FieldType::Float => format!("{{ let (raw, next) = wire::read_fixed32({b}, {p}); {p} = next; f32::from_bits(raw) }}", b = b, p = p),
+            FieldType::Sfixed32 => format!("{{ let (raw, next) = wire::read_fixed32({b}, {p}); {p} = next; raw as i32 }}", b = b, p = p),
+            _ => format!("{{ let (raw, next) = wire::read_fixed32({b}, {p}); {p} = next; raw }}", b = b, p = p),
+        },
+        WireCategory::Fixed64 => match *typ {
+            FieldType::Double => format!("{{ let (raw, next) = wire::read_fixed64({b}, {p}); {p} = next; f64::from_bits(raw) }}", b = b, p = p),
+            FieldType::Sfixed64 => format!("{{ let (raw, next) = wire::read_fixed64({b}, {p}); {p} = next; raw as i64 }}", b = b, p = p),
+            _ => format!("{{ let (raw, next) = wire::read_fixed64({b}, {p}); {p} = next; raw }}", b = b, p = p),
+        },
+        WireCategory::LengthDelimited => match *typ {
+            FieldType::String | FieldType::RefCountedString => format!(
+                "{{ let (raw, next) = wire::read_length_delimited({b}, {p}); {p} = next; String::from_utf8_lossy(raw).into_owned() }}",
+                b = b,
+                p = p
+            ),
+            FieldType::Bytes | FieldType::RefCountedBytes => format!(
+                "{{ let (raw, next) = wire::read_length_delimited({b}, {p}); {p} = next; raw.to_vec() }}",
+                b = b,
+                p = p
+            ),
+            FieldType::MessageOrEnum(ref name) => format!(
+                "{{ let (raw, next) = wire::read_length_delimited({b}, {p}); {p} = next; {ty}::merge_from(raw) }}",
+                b = b,
+                p = p,
+                ty = qualified_path(name, &ctx.package)
+            ),
+            _ => unreachable!(),
+        },
+    }
+}
+
+fn emit_field_decode_arm(out: &mut impl Write, field: &Field, ctx: &Ctx) -> Result<(), CodegenError> {
+    match field.typ {
+        FieldType::Map(ref kv) => emit_map_decode_arm(out, field, kv, ctx),
+        FieldType::Group(_) => emit_group_decode_arm(out, field),
+        ref other => emit_scalar_decode_arm(out, field, other, ctx),
+    }
+}
+
+fn emit_scalar_decode_arm(out: &mut impl Write, field: &Field, typ: &FieldType, ctx: &Ctx) -> Result<(), CodegenError> {
+    writeln!(out, "                {} => {{", field.number)?;
+    let category = wire_category(typ, ctx);
+    let packable = matches!(category, WireCategory::Varint | WireCategory::Fixed32 | WireCategory::Fixed64);
+    match field.rule {
+        Rule::Repeated if packable => {
+            // A reader must accept either a packed run or individual
+            // tag/value pairs, regardless of how the writer encoded it.
+            writeln!(out, "                    if wire_type == 2 {{")?;
+            writeln!(out, "                        let (body, next) = wire::read_length_delimited(bytes, pos);")?;
+            writeln!(out, "                        pos = next;")?;
+            writeln!(out, "                        let mut p = 0usize;")?;
+            writeln!(out, "                        while p < body.len() {{")?;
+            writeln!(out, "                            let value = {};", decode_value_expr(typ, ctx, "body", "p"))?;
+            writeln!(out, "                            result.{}.push(value);", field.name)?;
+            writeln!(out, "                        }}")?;
+            writeln!(out, "                    }} else {{")?;
+            writeln!(out, "                        let value = {};", decode_value_expr(typ, ctx, "bytes", "pos"))?;
+            writeln!(out, "                        result.{}.push(value);", field.name)?;
+            writeln!(out, "                    }}")?;
+        }
+        Rule::Repeated => {
+            writeln!(out, "                    let value = {};", decode_value_expr(typ, ctx, "bytes", "pos"))?;
+            writeln!(out, "                    result.{}.push(value);", field.name)?;
+        }
+        Rule::Optional => {
+            writeln!(out, "                    let value = {};", decode_value_expr(typ, ctx, "bytes", "pos"))?;
+            writeln!(out, "                    result.{} = Some(value);", field.name)?;
+        }
+        Rule::Required => {
+            writeln!(out, "                    let value = {};", decode_value_expr(typ, ctx, "bytes", "pos"))?;
+            writeln!(out, "                    result.{} = value;", field.name)?;
+        }
+    }
+    writeln!(out, "                }}")?;
+    Ok(())
+}
+
+fn emit_group_decode_arm(out: &mut impl Write, field: &Field) -> Result<(), CodegenError> {
+    writeln!(out, "                {} => {{", field.number)?;
+    writeln!(out, "                    let (body, next) = wire::read_group(bytes, pos, {});", field.number)?;
+    writeln!(out, "                    pos = next;")?;
+    writeln!(out, "                    let value = {}::merge_from(body);", group_struct_name(field))?;
+    match field.rule {
+        Rule::Repeated => writeln!(out, "                    result.{}.push(value);", field.name)?,
+        Rule::Optional => writeln!(out, "                    result.{} = Some(value);", field.name)?,
+        Rule::Required => writeln!(out, "                    result.{} = value;", field.name)?,
+    }
+    writeln!(out, "                }}")?;
+    Ok(())
+}
+
+fn emit_map_decode_arm(out: &mut impl Write, field: &Field, kv: &(FieldType, FieldType), ctx: &Ctx) -> Result<(), CodegenError> {
+    let key_ty = field_type_rust_name(&kv.0, ctx);
+    let val_ty = field_type_rust_name(&kv.1, ctx);
+    writeln!(out, "                {} => {{", field.number)?;
+    writeln!(out, "                    let (body, next) = wire::read_length_delimited(bytes, pos);")?;
+    writeln!(out, "                    pos = next;")?;
+    writeln!(out, "                    let mut key: {} = Default::default();", key_ty)?;
+    writeln!(out, "                    let mut value: {} = Default::default();", val_ty)?;
+    writeln!(out, "                    let mut p = 0usize;")?;
+    writeln!(out, "                    while p < body.len() {{")?;
+    writeln!(out, "                        let (entry_tag, next) = wire::read_varint(body, p);")?;
+    writeln!(out, "                        p = next;")?;
+    writeln!(out, "                        let entry_field = entry_tag >> 3;")?;
+    writeln!(out, "                        match entry_field {{")?;
+    writeln!(out, "                            1 => {{ key = {}; }}", decode_value_expr(&kv.0, ctx, "body", "p"))?;
+    writeln!(out, "                            2 => {{ value = {}; }}", decode_value_expr(&kv.1, ctx, "body", "p"))?;
+    writeln!(
+        out,
+        "                            _ => {{ let entry_wire_type = (entry_tag & 0x7) as u8; p = wire::skip_field(body, p, entry_wire_type, entry_field as u32); }}"
+    )?;
+    writeln!(out, "                        }}")?;
+    writeln!(out, "                    }}")?;
+    writeln!(out, "                    result.{}.insert(key, value);", field.name)?;
+    writeln!(out, "                }}")?;
+    Ok(())
+}
+
+fn emit_oneof_decode_arm(out: &mut impl Write, oneof: &OneOf, field: &Field, ctx: &Ctx) -> Result<(), CodegenError> {
+    writeln!(out, "                {} => {{", field.number)?;
+    match field.typ {
+        FieldType::Group(_) => {
+            writeln!(out, "                    let (body, next) = wire::read_group(bytes, pos, {});", field.number)?;
+            writeln!(out, "                    pos = next;")?;
+            writeln!(out, "                    let value = {}::merge_from(body);", group_struct_name(field))?;
+        }
+        FieldType::Map(..) => {
+            // `map` fields cannot be oneof members per the protobuf spec;
+            // there is nothing meaningful to decode into.
+            writeln!(out, "                    pos = wire::skip_field(bytes, pos, wire_type, field_number as u32);")?;
+            writeln!(out, "                }}")?;
+            return Ok(());
+        }
+        ref other => {
+            writeln!(out, "                    let value = {};", decode_value_expr(other, ctx, "bytes", "pos"))?;
+        }
+    }
+    writeln!(
+        out,
+        "                    result.{} = Some({}::{}(value));",
+        oneof.name,
+        oneof_enum_name(oneof),
+        capitalize(&field.name)
+    )?;
+    writeln!(out, "                }}")?;
+    Ok(())
+}
+
+fn emit_merge_from(out: &mut impl Write, message: &Message, ctx: &Ctx) -> Result<(), CodegenError> {
+    writeln!(out, "    pub fn merge_from(bytes: &[u8]) -> Self {{")?;
+    writeln!(out, "        let mut result = Self::default();")?;
+    writeln!(out, "        let mut pos = 0usize;")?;
+    writeln!(out, "        while pos < bytes.len() {{")?;
+    writeln!(out, "            let (tag, next) = wire::read_varint(bytes, pos);")?;
+    writeln!(out, "            pos = next;")?;
+    writeln!(out, "            let field_number = tag >> 3;")?;
+    writeln!(out, "            let wire_type = (tag & 0x7) as u8;")?;
+    writeln!(out, "            match field_number {{")?;
+    for field in &message.fields {
+        emit_field_decode_arm(out, field, ctx)?;
+    }
+    for oneof in &message.oneofs {
+        for field in &oneof.fields {
+            emit_oneof_decode_arm(out, oneof, field, ctx)?;
+        }
+    }
+    writeln!(out, "                _ => {{ pos = wire::skip_field(bytes, pos, wire_type, field_number as u32); }}")?;
+    writeln!(out, "            }}")?;
+    writeln!(out, "        }}")?;
+    writeln!(out, "        result")?;
+    writeln!(out, "    }}")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{EnumValue, Enumeration, FileDescriptor, Message, Field, FieldType, Rule};
+
+    fn render(message: &Message) -> String {
+        let symtab = SymbolTable::new();
+        let ctx = Ctx {
+            package: String::new(),
+            symtab: &symtab,
+        };
+        let mut buf = Vec::new();
+        write_message(&mut buf, message, &ctx).expect("generate");
+        String::from_utf8(buf).unwrap()
+    }
+
+    #[test]
+    fn test_scalar_field_mapping() {
+        let msg = Message {
+            name: "Sample".to_string(),
+            fields: vec![Field {
+                name: "age".to_string(),
+                rule: Rule::Optional,
+                typ: FieldType::Uint64,
+                number: 1,
+                default: None,
+                packed: None,
+                deprecated: false,
+                options: Vec::new(),
+            }],
+            ..Message::default()
+        };
+        let out = render(&msg);
+        assert!(out.contains("pub struct Sample"));
+        assert!(out.contains("pub age: Option<u64>,"));
+        assert!(out.contains("pub fn write_to(&self, out: &mut Vec<u8>)"));
+        assert!(out.contains("pub fn merge_from(bytes: &[u8]) -> Self"));
+    }
+
+    #[test]
+    fn test_repeated_message_field() {
+        let msg = Message {
+            name: "Container".to_string(),
+            fields: vec![Field {
+                name: "items".to_string(),
+                rule: Rule::Repeated,
+                typ: FieldType::MessageOrEnum("foo.Item".to_string()),
+                number: 1,
+                default: None,
+                packed: None,
+                deprecated: false,
+                options: Vec::new(),
+            }],
+            ..Message::default()
+        };
+        let out = render(&msg);
+        assert!(out.contains("pub items: Vec<foo::Item>,"));
+    }
+
+    #[test]
+    fn test_map_field() {
+        let msg = Message {
+            name: "Container".to_string(),
+            fields: vec![Field {
+                name: "by_id".to_string(),
+                rule: Rule::Optional,
+                typ: FieldType::Map(Box::new((FieldType::String, FieldType::Int32))),
+                number: 1,
+                default: None,
+                packed: None,
+                deprecated: false,
+                options: Vec::new(),
+            }],
+            ..Message::default()
+        };
+        let out = render(&msg);
+        assert!(out.contains("::std::collections::HashMap<String, i32>"));
+    }
+
+    #[test]
+    fn test_reserved_number_collision_errors() {
+        let msg = Message {
+            name: "Sample".to_string(),
+            fields: vec![Field {
+                name: "age".to_string(),
+                rule: Rule::Optional,
+                typ: FieldType::Uint64,
+                number: 4,
+                default: None,
+                packed: None,
+                deprecated: false,
+                options: Vec::new(),
+            }],
+            reserved_nums: vec![4..5],
+            ..Message::default()
+        };
+        let symtab = SymbolTable::new();
+        let ctx = Ctx {
+            package: String::new(),
+            symtab: &symtab,
+        };
+        match write_message(&mut Vec::new(), &msg, &ctx) {
+            Err(CodegenError::ReservedCollision { .. }) => (),
+            other => panic!("expected reserved collision, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_package_wraps_output_in_nested_mods() {
+        let desc = FileDescriptor {
+            package: "foo.bar".to_string(),
+            ..FileDescriptor::default()
+        };
+        let mut buf = Vec::new();
+        desc.generate_rust(&mut buf).expect("generate");
+        let out = String::from_utf8(buf).unwrap();
+        assert!(out.contains("pub mod foo {"));
+        assert!(out.contains("pub mod bar {"));
+    }
+
+    #[test]
+    fn test_package_with_message_brings_wire_into_scope() {
+        // A message (and thus a `wire::`-calling write_to/merge_from pair)
+        // nested inside package mods needs `use super::*;` at every mod
+        // level, or the bare `wire::` calls in its body can't resolve.
+        let desc = FileDescriptor {
+            package: "foo.bar".to_string(),
+            messages: vec![Message {
+                name: "Sample".to_string(),
+                fields: vec![Field {
+                    name: "id".to_string(),
+                    rule: Rule::Optional,
+                    typ: FieldType::Int32,
+                    number: 1,
+                    default: None,
+                    packed: None,
+                    deprecated: false,
+                    options: Vec::new(),
+                }],
+                ..Message::default()
+            }],
+            ..FileDescriptor::default()
+        };
+        let mut buf = Vec::new();
+        desc.generate_rust(&mut buf).expect("generate");
+        let out = String::from_utf8(buf).unwrap();
+        assert_eq!(2, out.matches("use super::*;").count());
+        assert!(out.contains("wire::write_varint"));
+    }
+
+    #[test]
+    fn test_qualified_path_strips_own_package_prefix() {
+        // A resolved reference into the file's own package (what
+        // `resolve::resolve` produces for every `MessageOrEnum`) must not
+        // re-include the package prefix: the generated code using this path
+        // is already nested inside `pub mod pkg { ... }`, so `pkg::Other`
+        // would mean "look for a `pkg` module from here", not "here".
+        assert_eq!("Other", qualified_path("pkg.Other", "pkg"));
+        assert_eq!("outer::Inner", qualified_path("pkg.Outer.Inner", "pkg"));
+    }
+
+    #[test]
+    fn test_nested_message_gets_its_own_mod_and_reference_matches() {
+        let desc = FileDescriptor {
+            messages: vec![Message {
+                name: "Outer".to_string(),
+                messages: vec![Message {
+                    name: "Inner".to_string(),
+                    ..Message::default()
+                }],
+                fields: vec![Field {
+                    name: "inner".to_string(),
+                    rule: Rule::Optional,
+                    typ: FieldType::MessageOrEnum("Outer.Inner".to_string()),
+                    number: 1,
+                    default: None,
+                    packed: None,
+                    deprecated: false,
+                    options: Vec::new(),
+                }],
+                ..Message::default()
+            }],
+            ..FileDescriptor::default()
+        };
+        let mut buf = Vec::new();
+        desc.generate_rust(&mut buf).expect("generate");
+        let out = String::from_utf8(buf).unwrap();
+        assert!(out.contains("pub mod outer {"));
+        assert!(out.contains("pub inner: Option<outer::Inner>,"));
+    }
+
+    #[test]
+    fn test_cross_file_enum_is_classified_as_enum_not_message() {
+        // `leaf.Color` isn't declared anywhere in this file - only in the
+        // caller-supplied symbol table, the way `ImportTree::generate_rust`
+        // builds one from `full_symbol_table`. Before `is_local_enum` looked
+        // the reference up in `symtab` directly, this field was guessed to be
+        // a message and the generated code called `leaf::Color::merge_from`,
+        // a method enums never get.
+        let color = Enumeration {
+            name: "Color".to_string(),
+            values: vec![EnumValue {
+                name: "RED".to_string(),
+                number: 0,
+                options: Vec::new(),
+            }],
+        };
+        let mut symtab = SymbolTable::new();
+        symtab.insert("leaf.Color".to_string(), Definition::Enumeration(&color));
+
+        let msg = Message {
+            name: "Root".to_string(),
+            fields: vec![Field {
+                name: "color".to_string(),
+                rule: Rule::Optional,
+                typ: FieldType::MessageOrEnum("leaf.Color".to_string()),
+                number: 1,
+                default: None,
+                packed: None,
+                deprecated: false,
+                options: Vec::new(),
+            }],
+            ..Message::default()
+        };
+        let ctx = Ctx {
+            package: String::new(),
+            symtab: &symtab,
+        };
+        let mut buf = Vec::new();
+        write_message(&mut buf, &msg, &ctx).expect("generate");
+        let out = String::from_utf8(buf).unwrap();
+        assert!(out.contains("pub color: Option<leaf::Color>,"));
+        assert!(out.contains("leaf::Color::from_i32"));
+        assert!(!out.contains("leaf::Color::merge_from"));
+    }
+
+    #[test]
+    fn test_oneof_with_group_member_does_not_panic() {
+        let oneof = OneOf {
+            name: "payload".to_string(),
+            fields: vec![Field {
+                name: "grp".to_string(),
+                rule: Rule::Optional,
+                typ: FieldType::Group(vec![]),
+                number: 2,
+                default: None,
+                packed: None,
+                deprecated: false,
+                options: Vec::new(),
+            }],
+        };
+        let symtab = SymbolTable::new();
+        let ctx = Ctx {
+            package: String::new(),
+            symtab: &symtab,
+        };
+        let mut buf = Vec::new();
+        write_oneof_enum(&mut buf, &oneof, &ctx).expect("generate");
+        let out = String::from_utf8(buf).unwrap();
+        assert!(out.contains("Grp(Grp),"));
+    }
+
+    #[test]
+    fn test_group_inside_oneof_gets_its_struct_emitted() {
+        let msg = Message {
+            name: "Sample".to_string(),
+            oneofs: vec![OneOf {
+                name: "payload".to_string(),
+                fields: vec![Field {
+                    name: "grp".to_string(),
+                    rule: Rule::Optional,
+                    typ: FieldType::Group(vec![Field {
+                        name: "x".to_string(),
+                        rule: Rule::Optional,
+                        typ: FieldType::Int32,
+                        number: 1,
+                        default: None,
+                        packed: None,
+                        deprecated: false,
+                        options: Vec::new(),
+                    }]),
+                    number: 2,
+                    default: None,
+                    packed: None,
+                    deprecated: false,
+                    options: Vec::new(),
+                }],
+            }],
+            ..Message::default()
+        };
+        let out = render(&msg);
+        assert!(out.contains("Grp(Grp),"));
+        assert!(out.contains("pub struct Grp {"));
+    }
+
+    #[test]
+    fn test_packed_repeated_scalar_uses_length_delimited_buffer() {
+        let msg = Message {
+            name: "Sample".to_string(),
+            fields: vec![Field {
+                name: "nums".to_string(),
+                rule: Rule::Repeated,
+                typ: FieldType::Int32,
+                number: 1,
+                default: None,
+                packed: None,
+                deprecated: false,
+                options: Vec::new(),
+            }],
+            ..Message::default()
+        };
+        let out = render(&msg);
+        assert!(out.contains("let mut packed = Vec::new();"));
+    }
+
+    #[test]
+    fn test_unpacked_repeated_scalar_writes_each_tag() {
+        let msg = Message {
+            name: "Sample".to_string(),
+            fields: vec![Field {
+                name: "nums".to_string(),
+                rule: Rule::Repeated,
+                typ: FieldType::Int32,
+                number: 1,
+                default: None,
+                packed: Some(false),
+                deprecated: false,
+                options: Vec::new(),
+            }],
+            ..Message::default()
+        };
+        let out = render(&msg);
+        assert!(!out.contains("let mut packed = Vec::new();"));
+        assert!(out.contains("for v in &self.nums"));
+    }
+}
@@ -0,0 +1,1016 @@
+//! Serializes a parsed `FileDescriptor` into the standard
+//! `google.protobuf.FileDescriptorProto` wire format, so that any tool which
+//! consumes the canonical descriptor format (protoc plugins, grpc-reflection,
+//! ...) can treat this crate as a drop-in front end.
+//!
+//! This is a hand-rolled encoder rather than a dependency on a generated
+//! `descriptor.proto` module: it only needs to *write* a small, fixed subset
+//! of the wire format, so a handful of varint/length-delimited helpers cover
+//! every field we emit.
+
+use super::resolve::{build_symbol_table, resolve_lenient, resolve_name, root_scope, Definition, SymbolTable};
+use super::{Enumeration, Field, FieldType, FileDescriptor, Message, Method, OneOf, OptionValue, Rule,
+    Service, Syntax};
+
+// FieldDescriptorProto.Type values, from descriptor.proto.
+const TYPE_DOUBLE: u64 = 1;
+const TYPE_FLOAT: u64 = 2;
+const TYPE_INT64: u64 = 3;
+const TYPE_UINT64: u64 = 4;
+const TYPE_INT32: u64 = 5;
+const TYPE_FIXED64: u64 = 6;
+const TYPE_FIXED32: u64 = 7;
+const TYPE_BOOL: u64 = 8;
+const TYPE_STRING: u64 = 9;
+const TYPE_GROUP: u64 = 10;
+const TYPE_MESSAGE: u64 = 11;
+const TYPE_BYTES: u64 = 12;
+const TYPE_UINT32: u64 = 13;
+const TYPE_ENUM: u64 = 14;
+const TYPE_SFIXED32: u64 = 15;
+const TYPE_SFIXED64: u64 = 16;
+const TYPE_SINT32: u64 = 17;
+const TYPE_SINT64: u64 = 18;
+
+// FieldDescriptorProto.Label values.
+const LABEL_OPTIONAL: u64 = 1;
+const LABEL_REQUIRED: u64 = 2;
+const LABEL_REPEATED: u64 = 3;
+
+impl FileDescriptor {
+    /// Serializes this descriptor into a `FileDescriptorProto` message.
+    ///
+    /// Field type references are resolved to their fully-qualified form
+    /// before encoding, so `type_name` always matches what a consumer of the
+    /// descriptor would expect: a leading `.` followed by the fully-qualified
+    /// name, not whatever bare name happened to appear in the `.proto`
+    /// source. Resolution is best-effort per field (see `resolve_lenient`):
+    /// a reference that can't be resolved - e.g. because it lives in an
+    /// import that isn't available here - is left as written, without
+    /// affecting any other, independently-resolvable field in the file.
+    ///
+    /// This only has `self`'s own declarations to resolve against, so a
+    /// reference into an imported file is left as written. Use
+    /// `file_descriptor_set_bytes` (or `to_descriptor_bytes_with` against an
+    /// `ImportTree::full_symbol_table`) when other files need to be in scope.
+    pub fn to_descriptor_bytes(&self) -> Vec<u8> {
+        let symtab = build_symbol_table(self);
+        self.to_descriptor_bytes_with(&symtab)
+    }
+
+    /// Like `to_descriptor_bytes`, but resolves type references against a
+    /// caller-supplied symbol table instead of one built from `self` alone -
+    /// e.g. `ImportTree::full_symbol_table`, so a reference into another file
+    /// in the same set resolves to its real fully-qualified `type_name`
+    /// instead of falling back to the name as written.
+    pub fn to_descriptor_bytes_with(&self, symtab: &SymbolTable) -> Vec<u8> {
+        let resolved = resolve_lenient(self, symtab);
+        let root_scope = root_scope(&resolved);
+
+        let mut out = Vec::new();
+        write_string_field(&mut out, 2, &resolved.package);
+        for dep in &resolved.import_paths {
+            write_string_field(&mut out, 3, dep);
+        }
+        for message in &resolved.messages {
+            write_message_field(&mut out, 4, &encode_message(message, &root_scope, symtab));
+        }
+        for e in &resolved.enums {
+            write_message_field(&mut out, 5, &encode_enum(e));
+        }
+        for service in &resolved.services {
+            write_message_field(&mut out, 6, &encode_service(service, &root_scope, symtab));
+        }
+        let file_options = encode_file_options(&resolved);
+        if !file_options.is_empty() {
+            write_message_field(&mut out, 8, &file_options);
+        }
+        write_string_field(
+            &mut out,
+            12,
+            match resolved.syntax {
+                Syntax::Proto2 => "proto2",
+                Syntax::Proto3 => "proto3",
+            },
+        );
+        out
+    }
+}
+
+/// Serializes several descriptors (e.g. a root file and everything it
+/// imports) into a single `FileDescriptorSet` message.
+///
+/// Every file's declarations are merged into one symbol table before any
+/// file is encoded, so a reference that crosses file boundaries (written
+/// package-qualified - see `ImportTree::full_symbol_table` - or by bare name
+/// between files that share a package) resolves to its real fully-qualified
+/// `type_name` instead of silently falling back to the name as written.
+pub fn file_descriptor_set_bytes(files: &[FileDescriptor]) -> Vec<u8> {
+    let mut symtab = SymbolTable::new();
+    for fd in files {
+        symtab.extend(build_symbol_table(fd));
+    }
+
+    let mut out = Vec::new();
+    for fd in files {
+        write_message_field(&mut out, 1, &fd.to_descriptor_bytes_with(&symtab));
+    }
+    out
+}
+
+fn encode_message(message: &Message, scope: &[String], symtab: &SymbolTable) -> Vec<u8> {
+    let mut out = Vec::new();
+    write_string_field(&mut out, 1, &message.name);
+
+    let mut own_scope = scope.to_vec();
+    own_scope.push(message.name.clone());
+
+    // Every oneof member field is also listed in the flat `field` list, with
+    // `oneof_index` pointing back at its entry in `oneof_decl`.
+    for field in &message.fields {
+        write_message_field(&mut out, 2, &encode_field(field, None, &own_scope, symtab));
+    }
+    for (oneof_index, oneof) in message.oneofs.iter().enumerate() {
+        for field in &oneof.fields {
+            write_message_field(
+                &mut out,
+                2,
+                &encode_field(field, Some(oneof_index as i64), &own_scope, symtab),
+            );
+        }
+    }
+
+    for nested in &message.messages {
+        write_message_field(&mut out, 3, &encode_message(nested, &own_scope, symtab));
+    }
+    for e in &message.enums {
+        write_message_field(&mut out, 4, &encode_enum(e));
+    }
+    for field in &message.fields {
+        if let FieldType::Map(ref kv) = field.typ {
+            write_message_field(&mut out, 3, &encode_map_entry(field, kv, &own_scope, symtab));
+        }
+    }
+    // `map` fields can't be oneof members per the protobuf spec, so only
+    // `group` needs to be checked across both the flat field list and every
+    // oneof's members.
+    for field in group_fields_of(message) {
+        if let FieldType::Group(ref group_fields) = field.typ {
+            let synthetic = Message {
+                name: group_type_name(field),
+                fields: group_fields.clone(),
+                ..Message::default()
+            };
+            write_message_field(&mut out, 3, &encode_message(&synthetic, &own_scope, symtab));
+        }
+    }
+
+    for range in &message.reserved_nums {
+        let mut range_bytes = Vec::new();
+        write_varint_field(&mut range_bytes, 1, range.start as i64);
+        write_varint_field(&mut range_bytes, 2, range.end as i64);
+        write_message_field(&mut out, 9, &range_bytes);
+    }
+    for name in &message.reserved_names {
+        write_string_field(&mut out, 10, name);
+    }
+
+    for oneof in &message.oneofs {
+        write_message_field(&mut out, 8, &encode_oneof(oneof));
+    }
+
+    let options = encode_message_options(message);
+    if !options.is_empty() {
+        write_message_field(&mut out, 7, &options);
+    }
+
+    out
+}
+
+/// Every `group`-typed field reachable from `message`'s own field list and
+/// from each of its oneofs - a oneof member that's a `group` still needs its
+/// own synthetic `DescriptorProto` entry, since `encode_field`'s `type_name`
+/// for it points at exactly that entry.
+fn group_fields_of(message: &Message) -> Vec<&Field> {
+    let mut fields: Vec<&Field> = message
+        .fields
+        .iter()
+        .filter(|f| matches!(f.typ, FieldType::Group(_)))
+        .collect();
+    for oneof in &message.oneofs {
+        fields.extend(oneof.fields.iter().filter(|f| matches!(f.typ, FieldType::Group(_))));
+    }
+    fields
+}
+
+fn encode_oneof(oneof: &OneOf) -> Vec<u8> {
+    let mut out = Vec::new();
+    write_string_field(&mut out, 1, &oneof.name);
+    out
+}
+
+/// A `map<K, V>` field is not its own wire type: it is rewritten as a
+/// `repeated` message field whose type points at a synthetic nested
+/// `<Field>Entry` message with `key`/`value` fields and `options.map_entry`
+/// set, exactly as protoc itself expands map fields.
+fn encode_map_entry(field: &Field, kv: &(FieldType, FieldType), scope: &[String], symtab: &SymbolTable) -> Vec<u8> {
+    let mut out = Vec::new();
+    write_string_field(&mut out, 1, &map_entry_type_name(field));
+
+    let key_field = Field {
+        name: "key".to_string(),
+        rule: Rule::Optional,
+        typ: kv.0.clone(),
+        number: 1,
+        default: None,
+        packed: None,
+        deprecated: false,
+        options: Vec::new(),
+    };
+    let value_field = Field {
+        name: "value".to_string(),
+        rule: Rule::Optional,
+        typ: kv.1.clone(),
+        number: 2,
+        default: None,
+        packed: None,
+        deprecated: false,
+        options: Vec::new(),
+    };
+    write_message_field(&mut out, 2, &encode_field(&key_field, None, scope, symtab));
+    write_message_field(&mut out, 2, &encode_field(&value_field, None, scope, symtab));
+
+    // MessageOptions.map_entry = true (field 7, bool).
+    let mut options = Vec::new();
+    write_bool_field(&mut options, 7, true);
+    write_message_field(&mut out, 7, &options);
+
+    out
+}
+
+fn map_entry_type_name(field: &Field) -> String {
+    format!("{}Entry", capitalize(&field.name))
+}
+
+fn group_type_name(field: &Field) -> String {
+    field.name.clone()
+}
+
+fn capitalize(name: &str) -> String {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().collect::<String>() + chars.as_str(),
+        None => String::new(),
+    }
+}
+
+fn encode_field(field: &Field, oneof_index: Option<i64>, scope: &[String], symtab: &SymbolTable) -> Vec<u8> {
+    let mut out = Vec::new();
+    write_string_field(&mut out, 1, &field.name);
+    write_varint_field(&mut out, 3, field.number as i64);
+
+    let label = match field.rule {
+        Rule::Optional => LABEL_OPTIONAL,
+        Rule::Required => LABEL_REQUIRED,
+        Rule::Repeated => LABEL_REPEATED,
+    };
+    let label = if let FieldType::Map(..) = field.typ { LABEL_REPEATED } else { label };
+    write_varint_field(&mut out, 4, label as i64);
+
+    let (wire_type, type_name) = field_wire_type(field, scope, symtab);
+    write_varint_field(&mut out, 5, wire_type as i64);
+    if let Some(name) = type_name {
+        write_string_field(&mut out, 6, &name);
+    }
+
+    if let Some(default) = field.default.as_ref() {
+        write_string_field(&mut out, 7, default);
+    }
+    // `oneof_index` is presence-tracked, not "0 means absent": a field in the
+    // first oneof (index 0) must still have its index written, so this can't
+    // reuse `write_varint_field`'s zero-skip.
+    if let Some(index) = oneof_index {
+        write_presence_varint_field(&mut out, 9, index);
+    }
+
+    let options = encode_field_options(field);
+    if !options.is_empty() {
+        write_message_field(&mut out, 8, &options);
+    }
+
+    out
+}
+
+/// `FieldOptions` for `field`: `packed` (field 2) is itself presence-tracked -
+/// `Some(false)` is an explicit `[packed = false]` and must be written just
+/// as faithfully as `Some(true)` - plus `deprecated` (field 3) and any
+/// remaining custom options as `uninterpreted_option` entries.
+fn encode_field_options(field: &Field) -> Vec<u8> {
+    let mut out = Vec::new();
+    if let Some(packed) = field.packed {
+        write_presence_bool_field(&mut out, 2, packed);
+    }
+    write_bool_field(&mut out, 3, field.deprecated);
+    for (name, value) in &field.options {
+        write_message_field(&mut out, 999, &encode_uninterpreted_option(name, value));
+    }
+    out
+}
+
+/// `MessageOptions` for `message`: the parser doesn't special-case a
+/// `deprecated` message option the way it does for fields, so it shows up as
+/// a plain entry in `message.options` - recognize it here and encode it into
+/// the dedicated `deprecated` field (3) instead of falling through to
+/// `uninterpreted_option`.
+fn encode_message_options(message: &Message) -> Vec<u8> {
+    let mut out = Vec::new();
+    for (name, value) in &message.options {
+        if name == "deprecated" {
+            if let OptionValue::Bool(b) = *value {
+                write_presence_bool_field(&mut out, 3, b);
+                continue;
+            }
+        }
+        write_message_field(&mut out, 999, &encode_uninterpreted_option(name, value));
+    }
+    out
+}
+
+/// `FileOptions` for `fd`, following the same `deprecated`-or-uninterpreted
+/// split as `encode_message_options`.
+fn encode_file_options(fd: &FileDescriptor) -> Vec<u8> {
+    let mut out = Vec::new();
+    for (name, value) in &fd.options {
+        if name == "deprecated" {
+            if let OptionValue::Bool(b) = *value {
+                write_presence_bool_field(&mut out, 23, b);
+                continue;
+            }
+        }
+        write_message_field(&mut out, 999, &encode_uninterpreted_option(name, value));
+    }
+    out
+}
+
+/// Encodes a single custom option as an `UninterpretedOption`: a dotted or
+/// parenthesized `(custom.ext)`-style name becomes a single `NamePart`
+/// (`is_extension` sees through the parens), and the value is written into
+/// whichever of `UninterpretedOption`'s scalar fields matches its type.
+/// `Aggregate` values have no single scalar slot to go in, so they're
+/// rendered into `aggregate_value` as a textproto-ish string, same as protoc
+/// does for `{ ... }` option literals.
+fn encode_uninterpreted_option(name: &str, value: &OptionValue) -> Vec<u8> {
+    let mut out = Vec::new();
+
+    let (is_extension, stripped) = if name.starts_with('(') && name.ends_with(')') {
+        (true, &name[1..name.len() - 1])
+    } else {
+        (false, name)
+    };
+    let mut name_part = Vec::new();
+    write_string_field(&mut name_part, 1, stripped);
+    write_presence_bool_field(&mut name_part, 2, is_extension);
+    write_message_field(&mut out, 2, &name_part);
+
+    match *value {
+        OptionValue::String(ref s) => write_string_field(&mut out, 7, s),
+        OptionValue::Identifier(ref s) => write_string_field(&mut out, 3, s),
+        OptionValue::Bool(b) => write_string_field(&mut out, 3, if b { "true" } else { "false" }),
+        OptionValue::Int(i) if i >= 0 => write_presence_varint_field(&mut out, 4, i),
+        OptionValue::Int(i) => write_presence_varint_field(&mut out, 5, i),
+        OptionValue::Float(f) => write_double_field(&mut out, 6, f),
+        OptionValue::Aggregate(ref fields) => write_string_field(&mut out, 8, &render_aggregate(fields)),
+    }
+
+    out
+}
+
+fn render_aggregate(fields: &[(String, OptionValue)]) -> String {
+    let rendered = fields
+        .iter()
+        .map(|(k, v)| format!("{}: {}", k, render_option_value(v)))
+        .collect::<Vec<_>>()
+        .join(", ");
+    format!("{{ {} }}", rendered)
+}
+
+fn render_option_value(value: &OptionValue) -> String {
+    match *value {
+        OptionValue::String(ref s) => format!("{:?}", s),
+        OptionValue::Identifier(ref s) => s.clone(),
+        OptionValue::Bool(b) => b.to_string(),
+        OptionValue::Int(i) => i.to_string(),
+        OptionValue::Float(f) => f.to_string(),
+        OptionValue::Aggregate(ref fields) => render_aggregate(fields),
+    }
+}
+
+/// Returns the `FieldDescriptorProto.type` value for `field`, plus the fully
+/// qualified `type_name` when it names a message or enum. `scope` is the
+/// fully-qualified path of the message `field` is declared in (package plus
+/// enclosing message names), needed to qualify the synthetic map-entry/group
+/// message names, which - unlike an ordinary `MessageOrEnum` reference -
+/// never go through `resolve_lenient` and so aren't fully-qualified already.
+fn field_wire_type(field: &Field, scope: &[String], symtab: &SymbolTable) -> (u64, Option<String>) {
+    match field.typ {
+        FieldType::Int32 => (TYPE_INT32, None),
+        FieldType::Int64 => (TYPE_INT64, None),
+        FieldType::Uint32 => (TYPE_UINT32, None),
+        FieldType::Uint64 => (TYPE_UINT64, None),
+        FieldType::Sint32 => (TYPE_SINT32, None),
+        FieldType::Sint64 => (TYPE_SINT64, None),
+        FieldType::Fixed32 => (TYPE_FIXED32, None),
+        FieldType::Sfixed32 => (TYPE_SFIXED32, None),
+        FieldType::Fixed64 => (TYPE_FIXED64, None),
+        FieldType::Sfixed64 => (TYPE_SFIXED64, None),
+        FieldType::Bool => (TYPE_BOOL, None),
+        FieldType::String | FieldType::RefCountedString => (TYPE_STRING, None),
+        FieldType::Bytes | FieldType::RefCountedBytes => (TYPE_BYTES, None),
+        FieldType::Float => (TYPE_FLOAT, None),
+        FieldType::Double => (TYPE_DOUBLE, None),
+        FieldType::Group(_) => (TYPE_GROUP, Some(qualify_in_scope(scope, &group_type_name(field)))),
+        FieldType::Map(..) => (TYPE_MESSAGE, Some(qualify_in_scope(scope, &map_entry_type_name(field)))),
+        FieldType::MessageOrEnum(ref name) => {
+            if is_enum(name, symtab) {
+                (TYPE_ENUM, Some(qualified_type_name(name)))
+            } else {
+                (TYPE_MESSAGE, Some(qualified_type_name(name)))
+            }
+        }
+    }
+}
+
+fn qualified_type_name(name: &str) -> String {
+    format!(".{}", name)
+}
+
+/// Qualifies a synthetic (not symbol-table-backed) type name with its
+/// enclosing `scope` - the package plus any enclosing message names, innermost
+/// last - the way protoc itself nests a map-entry or group message directly
+/// under the message that declares the field generating it.
+fn qualify_in_scope(scope: &[String], name: &str) -> String {
+    if scope.is_empty() {
+        format!(".{}", name)
+    } else {
+        format!(".{}.{}", scope.join("."), name)
+    }
+}
+
+/// Message/enum disambiguation for a resolved `MessageOrEnum` name: by the
+/// time `encode_message` runs, `to_descriptor_bytes` has already resolved
+/// every reference against this same `symtab` (see `resolve_lenient`), so a
+/// direct lookup is enough - no heuristic needed. A reference `resolve_lenient`
+/// couldn't resolve is left as written and won't be in `symtab` either, so it
+/// falls through to the message case - the same as before resolution ran.
+fn is_enum(name: &str, symtab: &SymbolTable) -> bool {
+    matches!(symtab.get(name), Some(Definition::Enumeration(_)))
+}
+
+/// Qualifies an `rpc` method's `request_type`/`response_type`: these aren't
+/// touched by `resolve_lenient` (that only walks message fields), so look the
+/// name up the same way `resolve_name` would for a field in the same scope,
+/// falling back to the name as written - unqualified except for the leading
+/// `.` every `type_name` needs - if it can't be resolved.
+fn qualify_method_type(name: &str, scope: &[String], symtab: &SymbolTable) -> String {
+    match resolve_name(name, scope, symtab) {
+        Ok(resolved) => qualified_type_name(&resolved),
+        Err(_) => qualified_type_name(name),
+    }
+}
+
+fn encode_enum(e: &Enumeration) -> Vec<u8> {
+    let mut out = Vec::new();
+    write_string_field(&mut out, 1, &e.name);
+    for value in &e.values {
+        let mut value_bytes = Vec::new();
+        write_string_field(&mut value_bytes, 1, &value.name);
+        write_varint_field(&mut value_bytes, 2, value.number as i64);
+        write_message_field(&mut out, 2, &value_bytes);
+    }
+    out
+}
+
+fn encode_service(service: &Service, root_scope: &[String], symtab: &SymbolTable) -> Vec<u8> {
+    let mut out = Vec::new();
+    write_string_field(&mut out, 1, &service.name);
+    for method in &service.methods {
+        write_message_field(&mut out, 2, &encode_method(method, root_scope, symtab));
+    }
+    out
+}
+
+/// A service always lives at the file's top level, so `method.request_type`/
+/// `response_type` resolve against `root_scope` the same way a top-level
+/// message's own fields do - there's no enclosing message to add.
+fn encode_method(method: &Method, root_scope: &[String], symtab: &SymbolTable) -> Vec<u8> {
+    let mut out = Vec::new();
+    write_string_field(&mut out, 1, &method.name);
+    write_string_field(&mut out, 2, &qualify_method_type(&method.request_type, root_scope, symtab));
+    write_string_field(&mut out, 3, &qualify_method_type(&method.response_type, root_scope, symtab));
+    if method.client_streaming {
+        write_bool_field(&mut out, 5, true);
+    }
+    if method.server_streaming {
+        write_bool_field(&mut out, 6, true);
+    }
+    out
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            break;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn write_tag(out: &mut Vec<u8>, field_number: u32, wire_type: u8) {
+    write_varint(out, ((field_number as u64) << 3) | wire_type as u64);
+}
+
+fn write_varint_field(out: &mut Vec<u8>, field_number: u32, value: i64) {
+    if value == 0 {
+        return;
+    }
+    write_tag(out, field_number, 0);
+    write_varint(out, value as u64);
+}
+
+/// Like `write_varint_field`, but always writes regardless of `value` - for
+/// fields where the value itself carries meaning even at zero (e.g.
+/// `oneof_index`, or an explicit `positive_int_value` of 0) and the
+/// zero-means-absent shortcut would silently drop them.
+fn write_presence_varint_field(out: &mut Vec<u8>, field_number: u32, value: i64) {
+    write_tag(out, field_number, 0);
+    write_varint(out, value as u64);
+}
+
+fn write_bool_field(out: &mut Vec<u8>, field_number: u32, value: bool) {
+    if !value {
+        return;
+    }
+    write_tag(out, field_number, 0);
+    write_varint(out, 1);
+}
+
+/// Like `write_bool_field`, but always writes regardless of `value` - for
+/// fields where an explicit `false` (e.g. `[packed = false]`) must be
+/// distinguished from the field being absent altogether.
+fn write_presence_bool_field(out: &mut Vec<u8>, field_number: u32, value: bool) {
+    write_tag(out, field_number, 0);
+    write_varint(out, if value { 1 } else { 0 });
+}
+
+fn write_double_field(out: &mut Vec<u8>, field_number: u32, value: f64) {
+    write_tag(out, field_number, 1);
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+fn write_string_field(out: &mut Vec<u8>, field_number: u32, value: &str) {
+    if value.is_empty() {
+        return;
+    }
+    write_tag(out, field_number, 2);
+    write_varint(out, value.len() as u64);
+    out.extend_from_slice(value.as_bytes());
+}
+
+fn write_message_field(out: &mut Vec<u8>, field_number: u32, body: &[u8]) {
+    write_tag(out, field_number, 2);
+    write_varint(out, body.len() as u64);
+    out.extend_from_slice(body);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{FieldType, Method, Rule, Service};
+
+    fn decode_varint(bytes: &[u8], pos: &mut usize) -> u64 {
+        let mut result = 0u64;
+        let mut shift = 0;
+        loop {
+            let byte = bytes[*pos];
+            *pos += 1;
+            result |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                break;
+            }
+            shift += 7;
+        }
+        result
+    }
+
+    #[test]
+    fn test_varint_roundtrip() {
+        for &value in &[0u64, 1, 127, 128, 300, 16384, u32::max_value() as u64] {
+            let mut out = Vec::new();
+            write_varint(&mut out, value);
+            let mut pos = 0;
+            assert_eq!(value, decode_varint(&out, &mut pos));
+            assert_eq!(out.len(), pos);
+        }
+    }
+
+    #[test]
+    fn test_simple_message_descriptor_roundtrips_name_and_field_count() {
+        let fd = FileDescriptor {
+            package: "pkg".to_string(),
+            messages: vec![Message {
+                name: "Sample".to_string(),
+                fields: vec![Field {
+                    name: "age".to_string(),
+                    rule: Rule::Optional,
+                    typ: FieldType::Uint64,
+                    number: 1,
+                    default: None,
+                    packed: None,
+                    deprecated: false,
+                    options: Vec::new(),
+                }],
+                ..Message::default()
+            }],
+            ..FileDescriptor::default()
+        };
+
+        let bytes = fd.to_descriptor_bytes();
+        assert!(!bytes.is_empty());
+
+        // package (field 2, wire type 2) should appear near the start.
+        assert_eq!(2 << 3 | 2, bytes[0]);
+    }
+
+    #[test]
+    fn test_map_field_expands_to_synthetic_entry_message() {
+        let message = Message {
+            name: "Container".to_string(),
+            fields: vec![Field {
+                name: "by_id".to_string(),
+                rule: Rule::Optional,
+                typ: FieldType::Map(Box::new((FieldType::String, FieldType::Int32))),
+                number: 1,
+                default: None,
+                packed: None,
+                deprecated: false,
+                options: Vec::new(),
+            }],
+            ..Message::default()
+        };
+        let fd = FileDescriptor::default();
+        let symtab = build_symbol_table(&fd);
+        let bytes = encode_message(&message, &Vec::new(), &symtab);
+        assert!(!bytes.is_empty());
+    }
+
+    #[test]
+    fn test_group_inside_oneof_gets_synthetic_message() {
+        let message = Message {
+            name: "Sample".to_string(),
+            oneofs: vec![OneOf {
+                name: "payload".to_string(),
+                fields: vec![Field {
+                    name: "grp".to_string(),
+                    rule: Rule::Optional,
+                    typ: FieldType::Group(vec![Field {
+                        name: "x".to_string(),
+                        rule: Rule::Optional,
+                        typ: FieldType::Int32,
+                        number: 1,
+                        default: None,
+                        packed: None,
+                        deprecated: false,
+                        options: Vec::new(),
+                    }]),
+                    number: 2,
+                    default: None,
+                    packed: None,
+                    deprecated: false,
+                    options: Vec::new(),
+                }],
+            }],
+            ..Message::default()
+        };
+        let fd = FileDescriptor::default();
+        let symtab = build_symbol_table(&fd);
+        let bytes = encode_message(&message, &Vec::new(), &symtab);
+
+        // The synthetic "Grp" nested_type (field 3, wire type 2) must be
+        // present alongside the flat field entry for the oneof member.
+        let mut pos = 0;
+        let mut nested_type_count = 0;
+        while pos < bytes.len() {
+            let tag = decode_varint(&bytes, &mut pos);
+            let field_number = tag >> 3;
+            let wire_type = tag & 0x7;
+            match wire_type {
+                0 => {
+                    decode_varint(&bytes, &mut pos);
+                }
+                2 => {
+                    let len = decode_varint(&bytes, &mut pos) as usize;
+                    if field_number == 3 {
+                        nested_type_count += 1;
+                    }
+                    pos += len;
+                }
+                _ => panic!("unexpected wire type {}", wire_type),
+            }
+        }
+        assert_eq!(1, nested_type_count, "expected exactly one synthetic nested message for the group");
+    }
+
+    #[test]
+    fn test_oneof_index_zero_is_still_written() {
+        let field = Field {
+            name: "a".to_string(),
+            rule: Rule::Optional,
+            typ: FieldType::Int32,
+            number: 1,
+            default: None,
+            packed: None,
+            deprecated: false,
+            options: Vec::new(),
+        };
+        let fd = FileDescriptor::default();
+        let symtab = build_symbol_table(&fd);
+        let bytes = encode_field(&field, Some(0), &Vec::new(), &symtab);
+
+        // oneof_index (field 9, wire type 0) must appear even though its
+        // value is 0.
+        let mut pos = 0;
+        let mut found = false;
+        while pos < bytes.len() {
+            let tag = decode_varint(&bytes, &mut pos);
+            let field_number = tag >> 3;
+            let wire_type = tag & 0x7;
+            if field_number == 9 && wire_type == 0 {
+                assert_eq!(0, decode_varint(&bytes, &mut pos));
+                found = true;
+                continue;
+            }
+            match wire_type {
+                0 => {
+                    decode_varint(&bytes, &mut pos);
+                }
+                2 => {
+                    let len = decode_varint(&bytes, &mut pos) as usize;
+                    pos += len;
+                }
+                _ => panic!("unexpected wire type {}", wire_type),
+            }
+        }
+        assert!(found, "expected to find oneof_index field in {:?}", bytes);
+    }
+
+    #[test]
+    fn test_explicit_packed_false_is_written() {
+        let field = Field {
+            name: "nums".to_string(),
+            rule: Rule::Repeated,
+            typ: FieldType::Int32,
+            number: 1,
+            default: None,
+            packed: Some(false),
+            deprecated: false,
+            options: Vec::new(),
+        };
+        let options = encode_field_options(&field);
+        // FieldOptions.packed (field 2, wire type 0) must appear even though
+        // its value is `false`.
+        assert_eq!(2 << 3 | 0, options[0]);
+    }
+
+    #[test]
+    fn test_to_descriptor_bytes_resolves_nested_type_references() {
+        let fd = FileDescriptor {
+            package: "pkg".to_string(),
+            messages: vec![Message {
+                name: "Outer".to_string(),
+                messages: vec![Message {
+                    name: "Inner".to_string(),
+                    ..Message::default()
+                }],
+                fields: vec![Field {
+                    name: "inner".to_string(),
+                    rule: Rule::Optional,
+                    typ: FieldType::MessageOrEnum("Inner".to_string()),
+                    number: 1,
+                    default: None,
+                    packed: None,
+                    deprecated: false,
+                    options: Vec::new(),
+                }],
+                ..Message::default()
+            }],
+            ..FileDescriptor::default()
+        };
+
+        let bytes = fd.to_descriptor_bytes();
+        let needle = b".pkg.Outer.Inner";
+        assert!(
+            bytes.windows(needle.len()).any(|w| w == needle),
+            "expected fully-qualified type_name in descriptor bytes"
+        );
+    }
+
+    #[test]
+    fn test_file_descriptor_set_bytes_resolves_cross_file_references() {
+        // `Leaf` is declared in its own file but shares `root`'s package, the
+        // ordinary way a large `package` gets split across several `.proto`
+        // files - a field may reference it by bare name without importing
+        // anything, same as if both messages had been declared in one file.
+        let leaf = FileDescriptor {
+            package: "pkg".to_string(),
+            messages: vec![Message {
+                name: "Leaf".to_string(),
+                ..Message::default()
+            }],
+            ..FileDescriptor::default()
+        };
+        let root = FileDescriptor {
+            package: "pkg".to_string(),
+            messages: vec![Message {
+                name: "Root".to_string(),
+                fields: vec![Field {
+                    name: "leaf".to_string(),
+                    rule: Rule::Optional,
+                    typ: FieldType::MessageOrEnum("Leaf".to_string()),
+                    number: 1,
+                    default: None,
+                    packed: None,
+                    deprecated: false,
+                    options: Vec::new(),
+                }],
+                ..Message::default()
+            }],
+            ..FileDescriptor::default()
+        };
+
+        // Resolved in isolation, `root` has no way to know `Leaf` is declared
+        // in another file, so it's left as written instead of qualified.
+        let isolated = root.to_descriptor_bytes();
+        let resolved_needle = b".pkg.Leaf";
+        assert!(
+            !isolated.windows(resolved_needle.len()).any(|w| w == resolved_needle),
+            "expected isolated resolution to leave the cross-file reference unqualified"
+        );
+
+        let set = file_descriptor_set_bytes(&[root, leaf]);
+        assert!(
+            set.windows(resolved_needle.len()).any(|w| w == resolved_needle),
+            "expected the set's shared symbol table to resolve the cross-file reference"
+        );
+    }
+
+    #[test]
+    fn test_one_unresolvable_field_does_not_degrade_sibling_fields() {
+        // `bad_field` can never resolve - `DoesNotExist` isn't declared
+        // anywhere in `fd` - but that must not stop `ok_field`, an entirely
+        // independent and resolvable reference, from getting its
+        // fully-qualified `type_name` too.
+        let fd = FileDescriptor {
+            package: "pkg".to_string(),
+            messages: vec![
+                Message {
+                    name: "Root".to_string(),
+                    fields: vec![
+                        Field {
+                            name: "ok_field".to_string(),
+                            rule: Rule::Optional,
+                            typ: FieldType::MessageOrEnum("Sibling".to_string()),
+                            number: 1,
+                            default: None,
+                            packed: None,
+                            deprecated: false,
+                            options: Vec::new(),
+                        },
+                        Field {
+                            name: "bad_field".to_string(),
+                            rule: Rule::Optional,
+                            typ: FieldType::MessageOrEnum("DoesNotExist".to_string()),
+                            number: 2,
+                            default: None,
+                            packed: None,
+                            deprecated: false,
+                            options: Vec::new(),
+                        },
+                    ],
+                    ..Message::default()
+                },
+                Message {
+                    name: "Sibling".to_string(),
+                    ..Message::default()
+                },
+            ],
+            ..FileDescriptor::default()
+        };
+
+        let bytes = fd.to_descriptor_bytes();
+        let needle = b".pkg.Sibling";
+        assert!(
+            bytes.windows(needle.len()).any(|w| w == needle),
+            "ok_field's resolvable reference should still be qualified despite bad_field failing to resolve"
+        );
+    }
+
+    #[test]
+    fn test_map_entry_type_name_is_package_and_message_qualified() {
+        let fd = FileDescriptor {
+            package: "pkg".to_string(),
+            messages: vec![Message {
+                name: "Container".to_string(),
+                fields: vec![Field {
+                    name: "counts".to_string(),
+                    rule: Rule::Repeated,
+                    typ: FieldType::Map(Box::new((FieldType::String, FieldType::Int32))),
+                    number: 1,
+                    default: None,
+                    packed: None,
+                    deprecated: false,
+                    options: Vec::new(),
+                }],
+                ..Message::default()
+            }],
+            ..FileDescriptor::default()
+        };
+
+        let bytes = fd.to_descriptor_bytes();
+        let needle = b".pkg.Container.CountsEntry";
+        assert!(
+            bytes.windows(needle.len()).any(|w| w == needle),
+            "expected the map field's type_name to be qualified with the enclosing package and message"
+        );
+    }
+
+    #[test]
+    fn test_group_type_name_is_package_and_message_qualified() {
+        let fd = FileDescriptor {
+            package: "pkg".to_string(),
+            messages: vec![Message {
+                name: "Container".to_string(),
+                fields: vec![Field {
+                    name: "grp".to_string(),
+                    rule: Rule::Optional,
+                    typ: FieldType::Group(vec![Field {
+                        name: "x".to_string(),
+                        rule: Rule::Optional,
+                        typ: FieldType::Int32,
+                        number: 1,
+                        default: None,
+                        packed: None,
+                        deprecated: false,
+                        options: Vec::new(),
+                    }]),
+                    number: 2,
+                    default: None,
+                    packed: None,
+                    deprecated: false,
+                    options: Vec::new(),
+                }],
+                ..Message::default()
+            }],
+            ..FileDescriptor::default()
+        };
+
+        let bytes = fd.to_descriptor_bytes();
+        let needle = b".pkg.Container.grp";
+        assert!(
+            bytes.windows(needle.len()).any(|w| w == needle),
+            "expected the group field's type_name to be qualified with the enclosing package and message"
+        );
+    }
+
+    #[test]
+    fn test_rpc_method_type_is_package_qualified() {
+        let fd = FileDescriptor {
+            package: "pkg".to_string(),
+            messages: vec![
+                Message { name: "HelloRequest".to_string(), ..Message::default() },
+                Message { name: "HelloReply".to_string(), ..Message::default() },
+            ],
+            services: vec![Service {
+                name: "Greeter".to_string(),
+                methods: vec![Method {
+                    name: "SayHello".to_string(),
+                    request_type: "HelloRequest".to_string(),
+                    client_streaming: false,
+                    response_type: "HelloReply".to_string(),
+                    server_streaming: false,
+                    options: Vec::new(),
+                }],
+            }],
+            ..FileDescriptor::default()
+        };
+
+        let bytes = fd.to_descriptor_bytes();
+        let needle = b".pkg.HelloRequest";
+        assert!(
+            bytes.windows(needle.len()).any(|w| w == needle),
+            "expected the rpc method's request_type to be qualified with the enclosing package"
+        );
+    }
+}
@@ -0,0 +1,370 @@
+//! Multi-file import resolution.
+//!
+//! `FileDescriptor::parse` only understands a single `.proto` file in
+//! isolation: `import` statements are recorded as bare strings in
+//! `FileDescriptor::import_paths` and never followed, so a reference like
+//! `FieldType::MessageOrEnum("ContainerForNested.NestedMessage")` can point at
+//! a message that was never parsed. This module walks the import graph
+//! starting from a root file, parsing every transitively imported file
+//! relative to a configurable set of include directories, and hands back the
+//! whole thing as an `ImportTree` so each file's `package` can be encapsulated
+//! into its own namespace rather than flattened.
+
+use std::collections::HashSet;
+use std::fmt;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use super::codegen::CodegenError;
+use super::resolve::{self, build_symbol_table, ResolveError, SymbolTable};
+use super::FileDescriptor;
+
+/// A parsed `.proto` file together with the parsed form of everything it
+/// (transitively) imports.
+#[derive(Debug)]
+pub struct ImportTree {
+    /// The absolute path this node was parsed from.
+    pub path: PathBuf,
+    pub descriptor: FileDescriptor,
+    /// One entry per `import` statement in `descriptor`, in declaration order.
+    pub imports: Vec<ImportTree>,
+}
+
+#[derive(Debug)]
+pub enum ImportError {
+    Io(PathBuf, io::Error),
+    Parse(PathBuf, String),
+    /// An `import "path";` could not be located in any include directory.
+    NotFound { path: String, searched: Vec<PathBuf> },
+    /// Following imports led back to a file already on the current path.
+    Circular(Vec<PathBuf>),
+}
+
+impl fmt::Display for ImportError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ImportError::Io(ref path, ref e) => write!(f, "could not read {}: {}", path.display(), e),
+            ImportError::Parse(ref path, ref detail) => {
+                write!(f, "failed to parse {}: {}", path.display(), detail)
+            }
+            ImportError::NotFound {
+                ref path,
+                ref searched,
+            } => write!(
+                f,
+                "import {:?} not found; searched {}",
+                path,
+                searched
+                    .iter()
+                    .map(|p| p.display().to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            ImportError::Circular(ref cycle) => write!(
+                f,
+                "circular import: {}",
+                cycle
+                    .iter()
+                    .map(|p| p.display().to_string())
+                    .collect::<Vec<_>>()
+                    .join(" -> ")
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ImportError {}
+
+impl FileDescriptor {
+    /// Parses `root` and every file it imports (transitively), searching the
+    /// importing file's own directory first, then `include_dirs`, for each
+    /// `import` path.
+    ///
+    /// Returns the whole import graph as an `ImportTree` rather than a single
+    /// merged `FileDescriptor`, so that each file's `package` keeps its own
+    /// scope instead of being flattened into the caller's.
+    pub fn parse_with_imports(root: &Path, include_dirs: &[PathBuf]) -> Result<ImportTree, ImportError> {
+        let mut stack = Vec::new();
+        parse_tree(root, include_dirs, &mut stack)
+    }
+}
+
+fn parse_tree(path: &Path, include_dirs: &[PathBuf], stack: &mut Vec<PathBuf>) -> Result<ImportTree, ImportError> {
+    let canonical = path
+        .canonicalize()
+        .map_err(|e| ImportError::Io(path.to_path_buf(), e))?;
+
+    if stack.contains(&canonical) {
+        let mut cycle = stack.clone();
+        cycle.push(canonical);
+        return Err(ImportError::Circular(cycle));
+    }
+
+    let contents = fs::read(&canonical).map_err(|e| ImportError::Io(canonical.clone(), e))?;
+    let descriptor = FileDescriptor::parse(&contents)
+        .map_err(|e| ImportError::Parse(canonical.clone(), format!("{:?}", e)))?;
+
+    stack.push(canonical.clone());
+
+    let parent_dir = canonical.parent().map(Path::to_path_buf);
+    let mut imports = Vec::with_capacity(descriptor.import_paths.len());
+    for import_path in &descriptor.import_paths {
+        let resolved = resolve_import(import_path, parent_dir.as_deref(), include_dirs)?;
+        imports.push(parse_tree(&resolved, include_dirs, stack)?);
+    }
+
+    stack.pop();
+
+    Ok(ImportTree {
+        path: canonical,
+        descriptor,
+        imports,
+    })
+}
+
+fn resolve_import(
+    import_path: &str,
+    importing_dir: Option<&Path>,
+    include_dirs: &[PathBuf],
+) -> Result<PathBuf, ImportError> {
+    let mut searched = Vec::new();
+
+    if let Some(dir) = importing_dir {
+        let candidate = dir.join(import_path);
+        if candidate.is_file() {
+            return Ok(candidate);
+        }
+        searched.push(candidate);
+    }
+
+    for dir in include_dirs {
+        let candidate = dir.join(import_path);
+        if candidate.is_file() {
+            return Ok(candidate);
+        }
+        searched.push(candidate);
+    }
+
+    Err(ImportError::NotFound {
+        path: import_path.to_string(),
+        searched,
+    })
+}
+
+impl ImportTree {
+    /// All packages reachable from this node, including its own, deduplicated
+    /// and in the order first encountered (depth-first).
+    pub fn packages(&self) -> Vec<String> {
+        let mut seen = HashSet::new();
+        let mut out = Vec::new();
+        self.collect_packages(&mut seen, &mut out);
+        out
+    }
+
+    fn collect_packages(&self, seen: &mut HashSet<String>, out: &mut Vec<String>) {
+        if seen.insert(self.descriptor.package.clone()) {
+            out.push(self.descriptor.package.clone());
+        }
+        for child in &self.imports {
+            child.collect_packages(seen, out);
+        }
+    }
+
+    /// Builds a single symbol table spanning this file and everything it
+    /// (transitively) imports. A cross-file reference like `leaf.Leaf` must
+    /// already be written package-qualified - protobuf has no "import *"
+    /// that brings an imported file's names into unqualified scope - so a
+    /// flat table merging every file's own `build_symbol_table` is all
+    /// `resolve::resolve`'s normal scope search needs to find it.
+    pub fn full_symbol_table(&self) -> SymbolTable {
+        let mut table = build_symbol_table(&self.descriptor);
+        for child in &self.imports {
+            table.extend(child.full_symbol_table());
+        }
+        table
+    }
+
+    /// Resolves every `FieldType::MessageOrEnum` reachable from this node -
+    /// and from everything it imports - against the whole import graph's
+    /// combined symbol table, returning a new tree whose descriptors carry
+    /// fully-qualified type references. Unlike `resolve::resolve` against a
+    /// single file's own `build_symbol_table`, which errors on such a
+    /// reference, this lets a reference into an imported file resolve.
+    pub fn resolve(&self) -> Result<ImportTree, ResolveError> {
+        let table = self.full_symbol_table();
+        self.resolve_with(&table)
+    }
+
+    fn resolve_with(&self, table: &SymbolTable) -> Result<ImportTree, ResolveError> {
+        let descriptor = resolve::resolve(&self.descriptor, table)?;
+        let mut imports = Vec::with_capacity(self.imports.len());
+        for child in &self.imports {
+            imports.push(child.resolve_with(table)?);
+        }
+        Ok(ImportTree {
+            path: self.path.clone(),
+            descriptor,
+            imports,
+        })
+    }
+
+    /// Generates Rust source for this node's own file (not its imports),
+    /// resolving type references against the whole import graph's combined
+    /// symbol table (see `full_symbol_table`), so a reference into an
+    /// imported file resolves to its real fully-qualified path instead of
+    /// being left as written, and an enum field declared in an imported file
+    /// is classified as one instead of being guessed to be a message. Unlike
+    /// `FileDescriptor::generate_rust` resolving a single file in isolation,
+    /// this is what `to_descriptor_bytes_with`/`file_descriptor_set_bytes`
+    /// (chunk0-6) already do for the descriptor-proto encoder.
+    pub fn generate_rust(&self, out: &mut impl Write) -> Result<(), CodegenError> {
+        let table = self.full_symbol_table();
+        self.descriptor.generate_rust_with(&table, out)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::FieldType;
+    use std::io::Write;
+
+    fn write_proto(dir: &Path, name: &str, contents: &str) -> PathBuf {
+        let path = dir.join(name);
+        let mut file = fs::File::create(&path).unwrap();
+        file.write_all(contents.as_bytes()).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_resolves_single_import() {
+        let dir = std::env::temp_dir().join("protobuf_parser_test_resolves_single_import");
+        fs::create_dir_all(&dir).unwrap();
+
+        write_proto(
+            &dir,
+            "leaf.proto",
+            r#"package leaf; message Leaf { optional int32 a = 1; }"#,
+        );
+        let root = write_proto(
+            &dir,
+            "root.proto",
+            r#"package root; import "leaf.proto"; message Root { optional int32 a = 1; }"#,
+        );
+
+        let tree = FileDescriptor::parse_with_imports(&root, &[]).expect("resolve");
+        assert_eq!(1, tree.imports.len());
+        assert_eq!("leaf", tree.imports[0].descriptor.package);
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_missing_import_reports_searched_dirs() {
+        let dir = std::env::temp_dir().join("protobuf_parser_test_missing_import");
+        fs::create_dir_all(&dir).unwrap();
+
+        let root = write_proto(
+            &dir,
+            "root.proto",
+            r#"import "does_not_exist.proto"; message Root {}"#,
+        );
+
+        match FileDescriptor::parse_with_imports(&root, &[]) {
+            Err(ImportError::NotFound { ref path, .. }) => assert_eq!("does_not_exist.proto", path),
+            other => panic!("expected NotFound, got {:?}", other),
+        }
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_resolve_rewrites_cross_file_and_local_references() {
+        let dir = std::env::temp_dir().join("protobuf_parser_test_resolve_cross_file");
+        fs::create_dir_all(&dir).unwrap();
+
+        write_proto(
+            &dir,
+            "leaf.proto",
+            r#"package leaf;
+            message Leaf {
+                message Nested { optional int32 x = 1; }
+                optional Nested inner = 1;
+            }"#,
+        );
+        let root = write_proto(
+            &dir,
+            "root.proto",
+            r#"package root;
+            import "leaf.proto";
+            message Root { optional leaf.Leaf payload = 1; }"#,
+        );
+
+        let tree = FileDescriptor::parse_with_imports(&root, &[]).expect("parse");
+        let resolved = tree.resolve().expect("resolve");
+
+        match resolved.descriptor.messages[0].fields[0].typ {
+            FieldType::MessageOrEnum(ref name) => assert_eq!("leaf.Leaf", name),
+            ref other => panic!("expected MessageOrEnum, got {:?}", other),
+        }
+        match resolved.imports[0].descriptor.messages[0].fields[0].typ {
+            FieldType::MessageOrEnum(ref name) => assert_eq!("leaf.Leaf.Nested", name),
+            ref other => panic!("expected MessageOrEnum, got {:?}", other),
+        }
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_circular_import_is_detected() {
+        let dir = std::env::temp_dir().join("protobuf_parser_test_circular_import");
+        fs::create_dir_all(&dir).unwrap();
+
+        let a = write_proto(&dir, "a.proto", r#"import "b.proto"; message A {}"#);
+        write_proto(&dir, "b.proto", r#"import "a.proto"; message B {}"#);
+
+        match FileDescriptor::parse_with_imports(&a, &[]) {
+            Err(ImportError::Circular(_)) => (),
+            other => panic!("expected Circular, got {:?}", other),
+        }
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_generate_rust_classifies_cross_file_enum() {
+        // `ImportTree::generate_rust` resolves against `full_symbol_table`
+        // rather than `root.proto`'s own declarations, so `leaf.Color` - an
+        // enum declared only in the imported file - is recognized as one:
+        // the field reads/writes it as a varint via `from_i32`, not as a
+        // nested message via `write_to`/`merge_from`, methods an enum never
+        // gets.
+        let dir = std::env::temp_dir().join("protobuf_parser_test_generate_rust_cross_file_enum");
+        fs::create_dir_all(&dir).unwrap();
+
+        write_proto(
+            &dir,
+            "leaf.proto",
+            r#"package leaf; enum Color { RED = 0; GREEN = 1; }"#,
+        );
+        let root = write_proto(
+            &dir,
+            "root.proto",
+            r#"package root;
+            import "leaf.proto";
+            message Root { optional leaf.Color color = 1; }"#,
+        );
+
+        let tree = FileDescriptor::parse_with_imports(&root, &[]).expect("parse");
+        let mut buf = Vec::new();
+        tree.generate_rust(&mut buf).expect("generate");
+        let out = String::from_utf8(buf).unwrap();
+
+        assert!(out.contains("pub color: Option<leaf::Color>,"));
+        assert!(out.contains("leaf::Color::from_i32"));
+        assert!(!out.contains("leaf::Color::merge_from"));
+
+        fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -1,8 +1,8 @@
 use std::str;
 use std::ops::Range;
 
-use super::{EnumValue, Enumeration, Extension, Field, FieldType, FileDescriptor, Message, OneOf,
-    Rule, Syntax};
+use super::{EnumValue, Enumeration, Extension, Field, FieldType, FileDescriptor, Message, Method,
+    OneOf, OptionValue, Rule, Service, Syntax};
 use nom::{digit, hex_digit, multispace};
 
 fn is_word(b: u8) -> bool {
@@ -111,15 +111,175 @@ named!(
     )
 );
 
+/// One `key = value` entry inside a `[...]` option bracket. The value is
+/// captured as raw text up to the next `,` or the closing `]` (so the
+/// bracket can hold several comma-separated entries without the first
+/// entry's value swallowing the rest), except when it's a `"`-quoted
+/// string, in which case it's scanned quote-aware the same way
+/// `option_value` does, so a literal `,` inside the quotes (e.g.
+/// `[json_name = "a,b"]`) doesn't truncate the value early.
 named!(
     key_val<(&str, &str)>,
     do_parse!(
-        tag!("[") >> many0!(br) >> key: word_ref >> many0!(br) >> tag!("=") >> many0!(br)
-            >> value: map_res!(is_not!("]"), str::from_utf8) >> tag!("]") >> many0!(br)
-            >> ((key, value.trim()))
+        many0!(br) >> key: word_ref >> many0!(br) >> tag!("=") >> many0!(br)
+            >> value:
+                map_res!(
+                    alt!(
+                        recognize!(do_parse!(
+                            tag!("\"") >> call!(until_unescaped_quote) >> tag!("\"") >> ()
+                        )) | is_not!(",]")
+                    ),
+                    str::from_utf8
+                ) >> many0!(br) >> ((key, value.trim()))
     )
 );
 
+/// A full `[key = value, key = value, ...]` field/enum-value option bracket.
+named!(
+    field_options<Vec<(&str, &str)>>,
+    do_parse!(
+        tag!("[") >> many0!(br) >> pairs: separated_list!(tag!(","), key_val) >> many0!(br)
+            >> tag!("]") >> many0!(br) >> (pairs)
+    )
+);
+
+/// `field_options` is optional (a field/enum-value may have no `[...]`
+/// bracket at all); this collapses the `None` case to an empty list. Named
+/// (rather than an inline closure passed to `map!`) because the closure's
+/// two elided `&str` lifetimes don't unify the way `map!`'s expansion
+/// needs, which fails to compile on stable Rust.
+fn unwrap_key_vals<'a>(o: Option<Vec<(&'a str, &'a str)>>) -> Vec<(&'a str, &'a str)> {
+    o.unwrap_or_default()
+}
+
+/// Scans a `"`-quoted string body up to (not including) the first
+/// unescaped `"`, resolving `\"` and `\\` along the way so an option value
+/// like `"a\"b"` doesn't truncate at the escaped quote. Any other backslash
+/// escape is passed through verbatim (untranslated) rather than rejected.
+fn until_unescaped_quote(input: &[u8]) -> ::nom::IResult<&[u8], Vec<u8>> {
+    let mut out = Vec::new();
+    let mut i = 0;
+    while i < input.len() {
+        match input[i] {
+            b'"' => return ::nom::IResult::Done(&input[i..], out),
+            b'\\' if i + 1 < input.len() => {
+                match input[i + 1] {
+                    b'"' => out.push(b'"'),
+                    b'\\' => out.push(b'\\'),
+                    other => {
+                        out.push(b'\\');
+                        out.push(other);
+                    }
+                }
+                i += 2;
+            }
+            b => {
+                out.push(b);
+                i += 1;
+            }
+        }
+    }
+    ::nom::IResult::Incomplete(::nom::Needed::Unknown)
+}
+
+named!(
+    quoted_string<String>,
+    do_parse!(
+        tag!("\"")
+            >> s: map_res!(call!(until_unescaped_quote), String::from_utf8)
+            >> tag!("\"") >> (s)
+    )
+);
+
+named!(
+    float<f64>,
+    map_res!(
+        recognize!(do_parse!(
+            opt!(alt!(tag!("-") | tag!("+"))) >> digit >> tag!(".") >> digit
+                >> opt!(do_parse!(
+                    alt!(tag!("e") | tag!("E")) >> opt!(alt!(tag!("-") | tag!("+"))) >> digit >> (())
+                )) >> (())
+        )),
+        |b: &[u8]| str::from_utf8(b).unwrap().parse::<f64>()
+    )
+);
+
+named!(
+    signed_integer<i64>,
+    map_res!(
+        recognize!(do_parse!(opt!(alt!(tag!("-") | tag!("+"))) >> digit >> (()))),
+        |b: &[u8]| str::from_utf8(b).unwrap().parse::<i64>()
+    )
+);
+
+/// The value half of an `option name = value;` or `key = value` field
+/// option, including the `{...}` aggregate syntax used for custom options
+/// like `option (custom.ext) = { foo: 1 bar: "x" };`.
+named!(
+    option_value<OptionValue>,
+    alt!(
+        option_aggregate => { OptionValue::Aggregate } |
+        quoted_string => { |s| OptionValue::String(s) } |
+        float => { |f| OptionValue::Float(f) } |
+        signed_integer => { |i| OptionValue::Int(i) } |
+        word => { |w: String| match w.as_str() {
+            "true" => OptionValue::Bool(true),
+            "false" => OptionValue::Bool(false),
+            _ => OptionValue::Identifier(w),
+        }}
+    )
+);
+
+named!(
+    option_aggregate_field<(String, OptionValue)>,
+    do_parse!(
+        key: option_name >> many0!(br) >> alt!(tag!(":") | tag!("=")) >> many0!(br)
+            >> value: option_value >> many0!(alt!(br | tag!(",") => { |_| () })) >> ((key, value))
+    )
+);
+
+named!(
+    option_aggregate<Vec<(String, OptionValue)>>,
+    do_parse!(
+        tag!("{") >> many0!(br) >> fields: many0!(option_aggregate_field) >> many0!(br) >> tag!("}")
+            >> (fields)
+    )
+);
+
+/// A plain field/file-level option name (`optimize_for`) or a parenthesized
+/// custom extension name, optionally followed by a nested path
+/// (`(custom.ext).nested_field`).
+named!(
+    option_name<String>,
+    alt!(
+        do_parse!(
+            tag!("(") >> many0!(br) >> name: word >> many0!(br) >> tag!(")") >> trailing: opt!(word)
+                >> (match trailing {
+                    Some(t) => format!("({}){}", name, t),
+                    None => format!("({})", name),
+                })
+        ) |
+        word
+    )
+);
+
+named!(
+    option_statement<(String, OptionValue)>,
+    do_parse!(
+        tag!("option") >> many1!(br) >> name: option_name >> many0!(br) >> tag!("=") >> many0!(br)
+            >> value: option_value >> many0!(br) >> tag!(";") >> ((name, value))
+    )
+);
+
+/// Parses the raw text captured by `key_val` for a field option that isn't
+/// one of the three hard-coded ones (`default`, `packed`, `deprecated`) into
+/// a structured `OptionValue`.
+fn parse_option_value(raw: &str) -> OptionValue {
+    option_value(raw.trim().as_bytes())
+        .to_full_result()
+        .expect("Cannot parse option value")
+}
+
 named!(
     rule<Rule>,
     alt!(tag!("optional") => { |_| Rule::Optional } |
@@ -192,7 +352,8 @@ named!(
     do_parse!(
         rule: opt!(rule) >> many0!(br) >> typ: field_type >> many1!(br) >> name: word >> many0!(br)
             >> tag!("=") >> many0!(br) >> number: integer >> many0!(br)
-            >> key_vals: many0!(key_val) >> many0!(br)
+            >> key_vals: map!(opt!(field_options), unwrap_key_vals)
+            >> many0!(br)
             >> group_fields: group_fields_or_semicolon >> ({
 
                 let typ = match (typ, group_fields) {
@@ -221,6 +382,11 @@ named!(
                         .find(|&&(k, _)| k == "deprecated")
                         .map_or(false, |&(_, v)| str::FromStr::from_str(v)
                             .expect("Cannot parse Deprecated value")),
+                    options: key_vals
+                        .iter()
+                        .filter(|&&(k, _)| k != "default" && k != "packed" && k != "deprecated")
+                        .map(|&(k, v)| (k.to_string(), parse_option_value(v)))
+                        .collect(),
                 }})
     )
 );
@@ -232,6 +398,7 @@ enum MessageEvent {
     ReservedNums(Vec<Range<i32>>),
     ReservedNames(Vec<String>),
     OneOf(OneOf),
+    Option(String, OptionValue),
     Ignore,
 }
 
@@ -239,6 +406,7 @@ named!(
     message_event<MessageEvent>,
     alt!(reserved_nums => { |r| MessageEvent::ReservedNums(r) } |
                                          reserved_names => { |r| MessageEvent::ReservedNames(r) } |
+                                         option_statement => { |(k, v)| MessageEvent::Option(k, v) } |
                                          message_field => { |f| MessageEvent::Field(f) } |
                                          message => { |m| MessageEvent::Message(m) } |
                                          enumerator => { |e| MessageEvent::Enumeration(e) } |
@@ -272,6 +440,7 @@ named!(
                     MessageEvent::Message(m) => msg.messages.push(m),
                     MessageEvent::Enumeration(e) => msg.enums.push(e),
                     MessageEvent::OneOf(o) => msg.oneofs.push(o),
+                    MessageEvent::Option(k, v) => msg.options.push((k, v)),
                     MessageEvent::Ignore => (),
                 }
             }
@@ -297,9 +466,16 @@ named!(
     enum_value<EnumValue>,
     do_parse!(
         name: word >> many0!(br) >> tag!("=") >> many0!(br) >> number: alt!(hex_integer | integer)
-            >> many0!(br) >> tag!(";") >> many0!(br) >> (EnumValue {
+            >> many0!(br)
+            >> key_vals: map!(opt!(field_options), unwrap_key_vals)
+            >> many0!(br) >> tag!(";") >> many0!(br)
+            >> (EnumValue {
             name: name,
             number: number,
+            options: key_vals
+                .into_iter()
+                .map(|(k, v)| (k.to_string(), parse_option_value(v)))
+                .collect(),
         })
     )
 );
@@ -316,16 +492,86 @@ named!(
     )
 );
 
+/// A request or response type on a `rpc`, together with whether it was
+/// preceded by the `stream` keyword.
+named!(
+    streaming_type<(bool, String)>,
+    do_parse!(
+        streaming: opt!(do_parse!(tag!("stream") >> many1!(br) >> (()))) >> many0!(br)
+            >> typ: word >> ((streaming.is_some(), typ))
+    )
+);
+
+enum MethodEvent {
+    Option(String, OptionValue),
+    Ignore,
+}
+
+named!(
+    method_event<MethodEvent>,
+    alt!(option_statement => { |(k, v)| MethodEvent::Option(k, v) } | br => { |_| MethodEvent::Ignore })
+);
+
+named!(
+    method_options_or_semicolon<Vec<(String, OptionValue)>>,
+    alt!(
+        tag!(";") => { |_| Vec::new() } |
+        do_parse!(
+            tag!("{") >> many0!(br) >> events: many0!(method_event) >> many0!(br) >> tag!("}")
+                >> (events
+                    .into_iter()
+                    .filter_map(|e| match e {
+                        MethodEvent::Option(k, v) => Some((k, v)),
+                        MethodEvent::Ignore => None,
+                    })
+                    .collect())
+        )
+    )
+);
+
+named!(
+    method<Method>,
+    do_parse!(
+        tag!("rpc") >> many1!(br) >> name: word >> many0!(br) >> tag!("(") >> many0!(br)
+            >> request: streaming_type >> many0!(br) >> tag!(")") >> many0!(br)
+            >> tag!("returns") >> many0!(br) >> tag!("(") >> many0!(br)
+            >> response: streaming_type >> many0!(br) >> tag!(")") >> many0!(br)
+            >> options: method_options_or_semicolon >> many0!(br) >> (Method {
+            name: name,
+            request_type: request.1,
+            client_streaming: request.0,
+            response_type: response.1,
+            server_streaming: response.0,
+            options: options,
+        })
+    )
+);
+
+enum ServiceEvent {
+    Method(Method),
+    Ignore,
+}
+
 named!(
-    option_ignore<()>,
-    do_parse!(tag!("option") >> many1!(br) >> take_until_and_consume!(";") >> ())
+    service_event<ServiceEvent>,
+    alt!(method => { |m| ServiceEvent::Method(m) } | br => { |_| ServiceEvent::Ignore })
 );
 
 named!(
-    service_ignore<()>,
+    service<Service>,
     do_parse!(
-        tag!("service") >> many1!(br) >> word >> many0!(br) >> tag!("{")
-            >> take_until_and_consume!("}") >> ()
+        tag!("service") >> many1!(br) >> name: word >> many0!(br) >> tag!("{") >> many0!(br)
+            >> events: many0!(service_event) >> many0!(br) >> tag!("}") >> many0!(br)
+            >> many0!(tag!(";")) >> (Service {
+            name: name,
+            methods: events
+                .into_iter()
+                .filter_map(|e| match e {
+                    ServiceEvent::Method(m) => Some(m),
+                    ServiceEvent::Ignore => None,
+                })
+                .collect(),
+        })
     )
 );
 
@@ -336,6 +582,8 @@ enum Event {
     Message(Message),
     Enum(Enumeration),
     Extensions(Vec<Extension>),
+    Option(String, OptionValue),
+    Service(Service),
     Ignore,
 }
 
@@ -347,8 +595,8 @@ named!(
             message => { |m| Event::Message(m) } |
             enumerator => { |e| Event::Enum(e) } |
             extensions => { |e| Event::Extensions(e) } |
-            option_ignore => { |_| Event::Ignore } |
-            service_ignore => { |_| Event::Ignore } |
+            option_statement => { |(k, v)| Event::Option(k, v) } |
+            service => { |s| Event::Service(s) } |
             br => { |_| Event::Ignore })
 );
 
@@ -363,6 +611,8 @@ named!(pub file_descriptor<FileDescriptor>,
                    Event::Message(m) => desc.messages.push(m),
                    Event::Enum(e) => desc.enums.push(e),
                    Event::Extensions(e) => desc.extensions.extend(e),
+                   Event::Option(k, v) => desc.options.push((k, v)),
+                   Event::Service(s) => desc.services.push(s),
                    Event::Ignore => (),
                }
            }
@@ -411,13 +661,12 @@ mod test {
     }
 
     #[test]
-    fn test_ignore() {
+    fn test_file_level_option() {
         let msg = r#"option optimize_for = SPEED;"#;
 
-        match option_ignore(msg.as_bytes()) {
-            ::nom::IResult::Done(_, _) => (),
-            e => panic!("Expecting done {:?}", e),
-        }
+        let (name, value) = option_statement(msg.as_bytes()).to_full_result().unwrap();
+        assert_eq!("optimize_for", name);
+        assert_eq!(OptionValue::Identifier("SPEED".to_string()), value);
     }
 
     #[test]
@@ -563,6 +812,52 @@ mod test {
         assert_eq!(r#""ab\nc d\xfeE\"g\'h\0\"z""#, mess.fields[0].default.as_ref().expect("default"));
     }
 
+    #[test]
+    fn test_field_option_beyond_the_three_hardcoded_ones_is_preserved() {
+        let msg = r#"message Sample {
+            optional string x = 1 [json_name = "x_name", deprecated = true];
+        }"#;
+
+        let mess = message(msg.as_bytes()).unwrap().1;
+        assert!(mess.fields[0].deprecated);
+        assert_eq!(
+            vec![("json_name".to_string(), OptionValue::String("x_name".to_string()))],
+            mess.fields[0].options
+        );
+    }
+
+    #[test]
+    fn test_quoted_option_string_with_escaped_quote_is_not_truncated() {
+        let msg = r#"message Sample {
+            optional int32 a = 1 [json_name = "a\"b"];
+        }"#;
+
+        let mess = message(msg.as_bytes()).unwrap().1;
+        assert_eq!(
+            vec![("json_name".to_string(), OptionValue::String("a\"b".to_string()))],
+            mess.fields[0].options
+        );
+    }
+
+    #[test]
+    fn test_message_level_aggregate_option() {
+        let msg = r#"message Sample {
+            option (custom.ext) = { foo: 1, bar: "x" };
+            optional int32 a = 1;
+        }"#;
+
+        let mess = message(msg.as_bytes()).unwrap().1;
+        assert_eq!(1, mess.options.len());
+        assert_eq!("(custom.ext)", mess.options[0].0);
+        assert_eq!(
+            OptionValue::Aggregate(vec![
+                ("foo".to_string(), OptionValue::Int(1)),
+                ("bar".to_string(), OptionValue::String("x".to_string())),
+            ]),
+            mess.options[0].1
+        );
+    }
+
     #[test]
     fn test_group() {
         let msg = r#"message MessageWithGroup {
@@ -620,4 +915,50 @@ mod test {
         assert_eq!("google.protobuf.MessageOptions", fd.extensions[2].extendee);
         assert_eq!(17003, fd.extensions[2].field.number);
     }
+
+    #[test]
+    fn test_service_with_streaming_and_option() {
+        let proto = r#"
+            service Greeter {
+                rpc SayHello (HelloRequest) returns (HelloReply);
+                rpc ChatStream (stream HelloRequest) returns (stream HelloReply) {
+                    option (google.api.http) = { post: "/v1/chat" };
+                }
+            }
+        "#;
+
+        let fd = FileDescriptor::parse(proto.as_bytes()).expect("fd");
+        assert_eq!(1, fd.services.len());
+        let service = &fd.services[0];
+        assert_eq!("Greeter", service.name);
+        assert_eq!(2, service.methods.len());
+
+        assert_eq!("SayHello", service.methods[0].name);
+        assert_eq!("HelloRequest", service.methods[0].request_type);
+        assert_eq!("HelloReply", service.methods[0].response_type);
+        assert!(!service.methods[0].client_streaming);
+        assert!(!service.methods[0].server_streaming);
+
+        assert_eq!("ChatStream", service.methods[1].name);
+        assert!(service.methods[1].client_streaming);
+        assert!(service.methods[1].server_streaming);
+        assert_eq!(1, service.methods[1].options.len());
+        assert_eq!("(google.api.http)", service.methods[1].options[0].0);
+    }
+
+    #[test]
+    fn test_service_body_option_with_closing_brace_does_not_truncate_method() {
+        let proto = r#"
+            service Greeter {
+                rpc SayHello (HelloRequest) returns (HelloReply) {
+                    option (custom.ext) = { foo: 1 };
+                }
+                rpc SayBye (ByeRequest) returns (ByeReply);
+            }
+        "#;
+
+        let fd = FileDescriptor::parse(proto.as_bytes()).expect("fd");
+        assert_eq!(2, fd.services[0].methods.len());
+        assert_eq!("SayBye", fd.services[0].methods[1].name);
+    }
 }
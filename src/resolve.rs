@@ -0,0 +1,356 @@
+//! Symbol-table type resolution.
+//!
+//! Parsing turns every bare type name that isn't a built-in scalar into
+//! `FieldType::MessageOrEnum(String)`, whether or not that name actually
+//! refers to anything. This pass borrows the `Env::lookup_definition(module,
+//! name)` design from preserves-schema: collect every `Message` and
+//! `Enumeration` declared anywhere in the file (including nested ones) into a
+//! symbol table keyed by fully-qualified name, then resolve each reference
+//! using protobuf's innermost-to-outermost scope search, rewriting the
+//! `MessageOrEnum` payload in place to the fully-qualified name it resolved
+//! to. Resolution never inlines a definition into the field that referenced
+//! it - messages may be self- or mutually-recursive, so a resolved field
+//! remains a symbolic link (a fully-qualified name) into the symbol table,
+//! not a copy of the target.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use super::{Enumeration, Field, FieldType, FileDescriptor, Message, OneOf};
+
+/// Where a `Message` or `Enumeration` came from, keyed by fully-qualified
+/// name in the symbol table built by `build_symbol_table`.
+#[derive(Debug, Clone, Copy)]
+pub enum Definition<'a> {
+    Message(&'a Message),
+    Enumeration(&'a Enumeration),
+}
+
+/// Maps a fully-qualified name (`package.Outer.Inner`) to the definition it
+/// names.
+pub type SymbolTable<'a> = HashMap<String, Definition<'a>>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResolveError {
+    /// The name as written in the `.proto` source.
+    pub name: String,
+    /// The fully-qualified candidate names that were tried - `name` prefixed
+    /// with each enclosing scope, innermost first, then `name` alone - in the
+    /// order they were searched.
+    pub scopes_searched: Vec<String>,
+}
+
+impl fmt::Display for ResolveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "could not resolve {:?}; searched scopes: {}",
+            self.name,
+            self.scopes_searched.join(", ")
+        )
+    }
+}
+
+impl std::error::Error for ResolveError {}
+
+/// Phase 1: collect every `Message` and `Enumeration` in `fd`, including
+/// nested ones, into a symbol table keyed by fully-qualified name.
+pub fn build_symbol_table(fd: &FileDescriptor) -> SymbolTable {
+    let mut table = HashMap::new();
+    let root_scope = root_scope(fd);
+
+    for message in &fd.messages {
+        collect_message(message, &root_scope, &mut table);
+    }
+    for enumeration in &fd.enums {
+        collect_enum(enumeration, &root_scope, &mut table);
+    }
+
+    table
+}
+
+fn collect_message<'a>(message: &'a Message, scope: &[String], table: &mut SymbolTable<'a>) {
+    let mut path = scope.to_vec();
+    path.push(message.name.clone());
+    table.insert(path.join("."), Definition::Message(message));
+
+    for nested in &message.messages {
+        collect_message(nested, &path, table);
+    }
+    for nested_enum in &message.enums {
+        collect_enum(nested_enum, &path, table);
+    }
+}
+
+fn collect_enum<'a>(e: &'a Enumeration, scope: &[String], table: &mut SymbolTable<'a>) {
+    let mut path = scope.to_vec();
+    path.push(e.name.clone());
+    table.insert(path.join("."), Definition::Enumeration(e));
+}
+
+/// Phase 2: resolve every `FieldType::MessageOrEnum` in `fd`'s messages
+/// (nested messages, oneofs, maps and groups included) against `table`,
+/// rewriting each one in place to the fully-qualified name it resolved to,
+/// and return the resolved clone. Fields of `extend` blocks are left as
+/// parsed. Only the clone is returned; callers look up what each name now
+/// refers to in the `table` they passed in.
+pub fn resolve<'a>(fd: &FileDescriptor, table: &SymbolTable<'a>) -> Result<FileDescriptor, ResolveError> {
+    let root_scope = root_scope(fd);
+    let mut resolved = fd.clone();
+    for message in &mut resolved.messages {
+        resolve_message(message, &root_scope, table, true)?;
+    }
+    Ok(resolved)
+}
+
+/// Like `resolve`, but a reference that can't be resolved is left exactly as
+/// parsed instead of aborting resolution for the rest of the file - every
+/// other, independently-resolvable reference still gets its fully-qualified
+/// name. Useful for a best-effort consumer (e.g. `descriptor_proto`) where
+/// one dangling reference - typically one that lives in an import that isn't
+/// available - shouldn't degrade every sibling field's `type_name` too.
+pub fn resolve_lenient(fd: &FileDescriptor, table: &SymbolTable) -> FileDescriptor {
+    let root_scope = root_scope(fd);
+    let mut resolved = fd.clone();
+    for message in &mut resolved.messages {
+        // `strict: false` never returns `Err`, so discarding the result here
+        // is safe - every field gets its own independent attempt.
+        let _ = resolve_message(message, &root_scope, table, false);
+    }
+    resolved
+}
+
+pub(crate) fn root_scope(fd: &FileDescriptor) -> Vec<String> {
+    if fd.package.is_empty() {
+        Vec::new()
+    } else {
+        fd.package.split('.').map(str::to_string).collect()
+    }
+}
+
+/// Walks `message` and everything nested inside it, resolving each
+/// `MessageOrEnum` reference in place. When `strict` is `true`, this matches
+/// `resolve`: the first unresolvable reference aborts the walk via `?`. When
+/// `strict` is `false`, it matches `resolve_lenient`: an unresolvable
+/// reference is left as written and the walk continues, so this never
+/// actually returns `Err` - callers that only want `resolve_lenient`'s
+/// behavior can discard the `Result`.
+fn resolve_message(message: &mut Message, scope: &[String], table: &SymbolTable, strict: bool) -> Result<(), ResolveError> {
+    let mut inner_scope = scope.to_vec();
+    inner_scope.push(message.name.clone());
+
+    for field in &mut message.fields {
+        resolve_field(field, &inner_scope, table, strict)?;
+    }
+    for oneof in &mut message.oneofs {
+        resolve_oneof(oneof, &inner_scope, table, strict)?;
+    }
+    for nested in &mut message.messages {
+        resolve_message(nested, &inner_scope, table, strict)?;
+    }
+    Ok(())
+}
+
+fn resolve_oneof(oneof: &mut OneOf, scope: &[String], table: &SymbolTable, strict: bool) -> Result<(), ResolveError> {
+    for field in &mut oneof.fields {
+        resolve_field(field, scope, table, strict)?;
+    }
+    Ok(())
+}
+
+fn resolve_field(field: &mut Field, scope: &[String], table: &SymbolTable, strict: bool) -> Result<(), ResolveError> {
+    resolve_field_type(&mut field.typ, scope, table, strict)
+}
+
+fn resolve_field_type(typ: &mut FieldType, scope: &[String], table: &SymbolTable, strict: bool) -> Result<(), ResolveError> {
+    match *typ {
+        FieldType::MessageOrEnum(ref mut name) => match resolve_name(name, scope, table) {
+            Ok(resolved) => *name = resolved,
+            Err(e) => {
+                if strict {
+                    return Err(e);
+                }
+            }
+        },
+        FieldType::Map(ref mut kv) => {
+            resolve_field_type(&mut kv.0, scope, table, strict)?;
+            resolve_field_type(&mut kv.1, scope, table, strict)?;
+        }
+        FieldType::Group(ref mut fields) => {
+            for field in fields {
+                resolve_field(field, scope, table, strict)?;
+            }
+        }
+        _ => (),
+    }
+    Ok(())
+}
+
+/// Searches `scope` from innermost to outermost, then the top level, for a
+/// definition named `name`. Returns the fully-qualified name it resolved to.
+pub(crate) fn resolve_name(name: &str, scope: &[String], table: &SymbolTable) -> Result<String, ResolveError> {
+    let mut searched = Vec::new();
+
+    for depth in (0..=scope.len()).rev() {
+        let candidate = if depth == 0 {
+            name.to_string()
+        } else {
+            format!("{}.{}", scope[..depth].join("."), name)
+        };
+        if table.contains_key(&candidate) {
+            return Ok(candidate);
+        }
+        searched.push(candidate);
+    }
+
+    Err(ResolveError {
+        name: name.to_string(),
+        scopes_searched: searched,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{FieldType, Rule};
+
+    fn field(name: &str, typ: FieldType) -> Field {
+        Field {
+            name: name.to_string(),
+            rule: Rule::Optional,
+            typ,
+            number: 1,
+            default: None,
+            packed: None,
+            deprecated: false,
+            options: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_resolves_sibling_message() {
+        let fd = FileDescriptor {
+            messages: vec![
+                Message {
+                    name: "A".to_string(),
+                    fields: vec![field("b", FieldType::MessageOrEnum("B".to_string()))],
+                    ..Message::default()
+                },
+                Message {
+                    name: "B".to_string(),
+                    ..Message::default()
+                },
+            ],
+            ..FileDescriptor::default()
+        };
+
+        let table = build_symbol_table(&fd);
+        let resolved = resolve(&fd, &table).expect("resolve");
+        match resolved.messages[0].fields[0].typ {
+            FieldType::MessageOrEnum(ref name) => assert_eq!("B", name),
+            ref other => panic!("expected MessageOrEnum, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_resolves_self_recursive_message() {
+        let fd = FileDescriptor {
+            messages: vec![Message {
+                name: "Node".to_string(),
+                fields: vec![field("next", FieldType::MessageOrEnum("Node".to_string()))],
+                ..Message::default()
+            }],
+            ..FileDescriptor::default()
+        };
+
+        let table = build_symbol_table(&fd);
+        let resolved = resolve(&fd, &table).expect("resolve");
+        match resolved.messages[0].fields[0].typ {
+            FieldType::MessageOrEnum(ref name) => assert_eq!("Node", name),
+            ref other => panic!("expected MessageOrEnum, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_nested_message_resolves_via_enclosing_scope() {
+        let fd = FileDescriptor {
+            package: "pkg".to_string(),
+            messages: vec![Message {
+                name: "Outer".to_string(),
+                messages: vec![Message {
+                    name: "Inner".to_string(),
+                    fields: vec![field("sibling", FieldType::MessageOrEnum("Sibling".to_string()))],
+                    ..Message::default()
+                }],
+                ..Message::default()
+            }],
+            enums: vec![],
+            ..FileDescriptor::default()
+        };
+        // "Sibling" lives alongside Outer, not Inner, so resolution must walk
+        // up a scope to find it.
+        let mut fd = fd;
+        fd.messages.push(Message {
+            name: "Sibling".to_string(),
+            ..Message::default()
+        });
+
+        let table = build_symbol_table(&fd);
+        let resolved = resolve(&fd, &table).expect("resolve");
+        match resolved.messages[0].messages[0].fields[0].typ {
+            FieldType::MessageOrEnum(ref name) => assert_eq!("pkg.Sibling", name),
+            ref other => panic!("expected MessageOrEnum, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_unresolved_name_reports_searched_scopes() {
+        let fd = FileDescriptor {
+            messages: vec![Message {
+                name: "A".to_string(),
+                fields: vec![field("b", FieldType::MessageOrEnum("Missing".to_string()))],
+                ..Message::default()
+            }],
+            ..FileDescriptor::default()
+        };
+
+        let table = build_symbol_table(&fd);
+        let err = resolve(&fd, &table).unwrap_err();
+        assert_eq!("Missing", err.name);
+        assert!(!err.scopes_searched.is_empty());
+    }
+
+    #[test]
+    fn test_resolve_lenient_keeps_other_fields_resolved_despite_one_dangling_reference() {
+        let fd = FileDescriptor {
+            package: "pkg".to_string(),
+            messages: vec![
+                Message {
+                    name: "A".to_string(),
+                    fields: vec![
+                        field("ok_field", FieldType::MessageOrEnum("Sibling".to_string())),
+                        field("bad_field", FieldType::MessageOrEnum("Missing".to_string())),
+                    ],
+                    ..Message::default()
+                },
+                Message {
+                    name: "Sibling".to_string(),
+                    ..Message::default()
+                },
+            ],
+            ..FileDescriptor::default()
+        };
+
+        let table = build_symbol_table(&fd);
+        let resolved = resolve_lenient(&fd, &table);
+
+        match resolved.messages[0].fields[0].typ {
+            FieldType::MessageOrEnum(ref name) => assert_eq!("pkg.Sibling", name),
+            ref other => panic!("expected MessageOrEnum, got {:?}", other),
+        }
+        match resolved.messages[0].fields[1].typ {
+            FieldType::MessageOrEnum(ref name) => assert_eq!("Missing", name),
+            ref other => panic!("expected MessageOrEnum, got {:?}", other),
+        }
+    }
+}